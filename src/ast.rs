@@ -1,69 +1,190 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::ops::Deref;
+
+use crate::errors::Span;
+use crate::render::{render_tag, Mode, RenderOptions};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    pub nodes: Vec<Node>,
+}
+
+impl Document {
+    /// The doctype declared at the start of the document, if any
+    pub fn doctype(&self) -> Option<&Doctype> {
+        self.nodes.iter().find_map(|node| match node {
+            Node::Doctype(doctype) => Some(doctype),
+            _ => None,
+        })
+    }
+}
+
+/// Everything that can appear in a document, in the order it was written in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Element(Tag),
+    Text(Text),
+    Comment(Comment),
+    Doctype(Doctype),
+    /// A line that couldn't be parsed, along with the block indented under it. The error itself
+    /// is reported next to the document.
+    Error(Span),
+}
+
+impl Node {
+    /// Where the node was written in the source, children included
+    pub fn span(&self) -> Span {
+        match self {
+            Node::Element(tag) => tag.span.clone(),
+            Node::Text(text) => text.span.clone(),
+            Node::Comment(comment) => comment.span.clone(),
+            Node::Doctype(doctype) => doctype.span.clone(),
+            Node::Error(span) => span.clone(),
+        }
+    }
+}
+
+/// A value together with the part of the source it was parsed from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub span: Span,
+}
+
+impl<T> Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+/// Text between elements: what follows a tag on its line, `| text` lines and the block under
+/// `tag.`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Text {
+    /// Multi-line text keeps its line breaks
+    pub text: String,
+    /// Whether `text` is written out as-is instead of being escaped (`p!= <b>hi</b>`)
+    pub raw: bool,
+    pub span: Span,
+}
+
+/// `// text` or `//- text`, optionally followed by an indented block that's part of the comment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    /// Everything after the `//`/`//-`, with the lines of the block below it (if any) after a
+    /// line break
+    pub text: String,
+    /// `//` comments end up in the output as `<!-- -->`, `//-` comments are only kept in the AST
+    pub buffered: bool,
+    pub span: Span,
+}
+
+/// `doctype html`, `doctype xml`, `doctype strict`, ... or any custom `doctype <whatever>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Doctype {
+    pub value: String,
+    pub span: Span,
+}
+
+impl Doctype {
+    /// The declaration that ends up in the output, with the usual shorthands expanded
+    pub fn declaration(&self) -> Cow<'_, str> {
+        Cow::Borrowed(match self.value.as_str() {
+            "html" => "<!DOCTYPE html>",
+            "xml" => r#"<?xml version="1.0" encoding="utf-8" ?>"#,
+            "transitional" => {
+                r#"<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Transitional//EN" "http://www.w3.org/TR/xhtml1/DTD/xhtml1-transitional.dtd">"#
+            }
+            "strict" => {
+                r#"<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Strict//EN" "http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd">"#
+            }
+            "frameset" => {
+                r#"<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Frameset//EN" "http://www.w3.org/TR/xhtml1/DTD/xhtml1-frameset.dtd">"#
+            }
+            "1.1" => {
+                r#"<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.1//EN" "http://www.w3.org/TR/xhtml11/DTD/xhtml11.dtd">"#
+            }
+            "basic" => {
+                r#"<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML Basic 1.1//EN" "http://www.w3.org/TR/xhtml-basic/xhtml-basic11.dtd">"#
+            }
+            "mobile" => {
+                r#"<!DOCTYPE html PUBLIC "-//WAPFORUM//DTD XHTML Mobile 1.2//EN" "http://www.openmobilealliance.org/tech/DTD/xhtml-mobile12.dtd">"#
+            }
+            "plist" => {
+                r#"<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">"#
+            }
+            custom => return Cow::Owned(format!("<!DOCTYPE {custom}>")),
+        })
+    }
+
+    /// The rendering mode the rest of the document uses unless told otherwise: only HTML5
+    /// documents are terse, everything else is XML
+    pub fn mode(&self) -> Mode {
+        if self.value == "html" {
+            Mode::Html
+        } else {
+            Mode::Xml
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Tag {
     pub name: String,
-    pub attrs: HashMap<String, Option<String>>,
-    pub id: Option<String>,
-    pub classes: Vec<String>,
-    pub children: Vec<Tag>,
-    pub content: Option<String>,
+    /// Attributes in the order they were written in the source
+    pub attrs: Vec<Attribute>,
+    /// From `#id` or an `id` attribute
+    pub id: Option<Spanned<String>>,
+    /// From `.class` shorthands and `class` attributes, without duplicates
+    pub classes: Vec<Spanned<String>>,
+    pub children: Vec<Node>,
+    /// Written as `tag/`, so it's closed right away like a void element (`<path/>`)
+    pub self_closing: bool,
+    /// The tag's line and all of its children
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attribute {
+    pub name: String,
+    /// `None` for boolean attributes like `disabled`
+    pub value: Option<AttrValue>,
+    pub span: Span,
 }
 
-const VOID_ELEMENTS: &[&str] = &[
-    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
-    "track", "wbr",
-];
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttrValue {
+    pub value: String,
+    /// Whether `value` is written out as-is instead of being escaped (`a(href!="...")`)
+    pub raw: bool,
+}
 
-impl Tag {
-    pub fn htmlify(&self) -> String {
-        let Self {
-            name,
-            attrs,
-            id,
-            classes,
-            children,
-            content,
-        } = self;
-        let classes = classes.join(" ");
-
-        let parsed_attrs = attrs
-            .iter()
-            // remap these so we can append id and classes by chaining and not a new hashmap
-            .map(|(key, value)| (key.as_str(), value.as_deref()))
-            .chain(id.as_deref().map(|id| ("id", Some(id))))
-            .chain(if classes.is_empty() {
-                None
-            } else {
-                Some(("class", Some(classes.as_str())))
-            })
-            .map(|(key, value)| {
-                if let Some(value) = value {
-                    format!(" {key}=\"{value}\"")
-                } else {
-                    key.to_string()
-                }
-            })
-            .collect::<Vec<_>>()
-            // first one starts with space, which separates it from {name}, others spaced normally
-            .join("");
-
-        if VOID_ELEMENTS.contains(&name.as_str()) {
-            debug_assert!(children.is_empty(), "Void elements must not have children");
-            debug_assert!(content.is_none(), "Void elements must not have content");
-
-            return format!("<{name}{parsed_attrs}/>");
+impl AttrValue {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self {
+            value: value.into(),
+            raw: false,
         }
+    }
 
-        let parsed_children = children
-            .iter()
-            .map(|child| child.htmlify())
-            .collect::<Vec<_>>()
-            .join("");
+    pub fn raw(value: impl Into<String>) -> Self {
+        Self {
+            value: value.into(),
+            raw: true,
+        }
+    }
+}
+
+impl Tag {
+    pub fn htmlify(&self) -> String {
+        self.htmlify_with(&RenderOptions::default())
+    }
 
-        format!(
-            "<{name}{parsed_attrs}>{content}{parsed_children}</{name}>",
-            content = content.as_deref().unwrap_or(""),
-        )
+    pub fn htmlify_with(&self, options: &RenderOptions) -> String {
+        let mut html = String::new();
+        render_tag(&mut html, self, options).expect("writing to a String can't fail");
+        html
     }
 }
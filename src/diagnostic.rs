@@ -0,0 +1,194 @@
+use std::fmt;
+
+use crate::errors::{Error, Span};
+
+/// A line and column in the source, both starting at 1. Columns count characters, not bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    pub fn of(source: &str, offset: usize) -> Self {
+        let offset = clamp(source, offset);
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+
+        Self {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..]
+                .chars()
+                .filter(|&char| char != BOM)
+                .count()
+                + 1,
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// An error together with the source it points into, displayed like
+///
+/// ```text
+/// error[P0001]: expected `=` or `)`, found a line break
+///  --> index.purgs:3:12
+///   |
+/// 3 |     a(href "/")
+///   |            ^
+/// ```
+pub struct Diagnostic<'a> {
+    error: &'a Error,
+    file_name: &'a str,
+    source: &'a str,
+}
+
+impl<'a> Diagnostic<'a> {
+    pub fn new(error: &'a Error, file_name: &'a str, source: &'a str) -> Self {
+        Self {
+            error,
+            file_name,
+            source,
+        }
+    }
+
+    /// Where the error starts
+    pub fn location(&self) -> Location {
+        Location::of(self.source, self.error.span().start)
+    }
+
+    /// Writes the diagnostic as a single line of JSON for editors and CI:
+    ///
+    /// ```text
+    /// {"code":"P0005","severity":"error","message":"duplicate attribute `href`","file":"index.purgs",
+    ///  "span":{"start":12,"end":21,"line":1,"column":13,"end_line":1,"end_column":22},
+    ///  "notes":[{"message":"first set here","span":{...}}]}
+    /// ```
+    ///
+    /// Fields are only ever added to this, never changed or removed.
+    pub fn write_json<W: fmt::Write>(&self, out: &mut W) -> fmt::Result {
+        write!(
+            out,
+            r#"{{"code":"{}","severity":"error","message":"#,
+            self.error.code()
+        )?;
+        write_json_string(out, &self.error.to_string())?;
+        out.write_str(r#","file":"#)?;
+        write_json_string(out, self.file_name)?;
+        out.write_str(r#","span":"#)?;
+        self.write_json_span(out, self.error.span())?;
+
+        out.write_str(r#","notes":["#)?;
+        if let Some((span, note)) = self.error.related() {
+            out.write_str(r#"{"message":"#)?;
+            write_json_string(out, note)?;
+            out.write_str(r#","span":"#)?;
+            self.write_json_span(out, span)?;
+            out.write_char('}')?;
+        }
+        out.write_str("]}")
+    }
+
+    fn write_json_span<W: fmt::Write>(&self, out: &mut W, span: Span) -> fmt::Result {
+        let start = Location::of(self.source, span.start);
+        let end = Location::of(self.source, span.end);
+
+        write!(
+            out,
+            r#"{{"start":{},"end":{},"line":{},"column":{},"end_line":{},"end_column":{}}}"#,
+            span.start, span.end, start.line, start.column, end.line, end.column
+        )
+    }
+
+    fn snippet(&self, f: &mut fmt::Formatter<'_>, span: Span) -> fmt::Result {
+        let start = clamp(self.source, span.start);
+        let location = Location::of(self.source, start);
+
+        let line_start = self.source[..start]
+            .rfind('\n')
+            .map_or(0, |newline| newline + 1);
+        let line_end = self.source[start..]
+            .find('\n')
+            .map_or(self.source.len(), |newline| start + newline);
+        let line = self.source[line_start..line_end]
+            .trim_start_matches(BOM)
+            .trim_end_matches('\r');
+
+        // only the first line of a span is underlined
+        let end = span.end.clamp(start, line_end);
+        let padding = display_width(&self.source[line_start..start]);
+        let underline = display_width(&self.source[start..end]).max(1);
+
+        let gutter = " ".repeat(location.line.to_string().len());
+        writeln!(f, "{gutter}--> {}:{location}", self.file_name)?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{} | {}", location.line, line.replace('\t', TAB))?;
+        write!(
+            f,
+            "{gutter} | {}{}",
+            " ".repeat(padding),
+            "^".repeat(underline)
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "error[{}]: {}", self.error.code(), self.error)?;
+        self.snippet(f, self.error.span())?;
+
+        if let Some((span, note)) = self.error.related() {
+            writeln!(f)?;
+            writeln!(f, "note: {note}")?;
+            self.snippet(f, span)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_json_string<W: fmt::Write>(out: &mut W, string: &str) -> fmt::Result {
+    out.write_char('"')?;
+    for char in string.chars() {
+        match char {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\r' => out.write_str("\\r")?,
+            '\t' => out.write_str("\\t")?,
+            char if char.is_control() => write!(out, "\\u{:04x}", char as u32)?,
+            char => out.write_char(char)?,
+        }
+    }
+    out.write_char('"')
+}
+
+/// Tabs are shown as this many spaces, so the underline lines up no matter the terminal's tab
+/// width
+const TAB: &str = "    ";
+
+/// A byte order mark at the start of the file, which doesn't take up a column
+const BOM: char = '\u{feff}';
+
+fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|char| match char {
+            '\t' => TAB.len(),
+            BOM => 0,
+            _ => 1,
+        })
+        .sum()
+}
+
+/// Keeps `offset` inside `source`. Errors at the very end of a file that ends in a line break
+/// point at the end of the last line instead of the empty line after it.
+fn clamp(source: &str, offset: usize) -> usize {
+    match source.strip_suffix('\n') {
+        Some(rest) if offset >= source.len() => rest.strip_suffix('\r').unwrap_or(rest).len(),
+        _ => offset.min(source.len()),
+    }
+}
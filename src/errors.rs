@@ -1,15 +1,16 @@
+use crate::diagnostic::Diagnostic;
 use crate::lexer::Token;
-use aott::error::Filtering;
 use aott::prelude::InputType;
-use std::fmt::Debug;
+use itertools::Itertools;
+use std::fmt::{Debug, Display};
 use std::ops::Range;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("Parser error: {_0}")]
+    #[error("{_0}")]
     Parser(#[from] ParserError),
-    #[error("Lexer error: {_0}")]
+    #[error("{_0}")]
     Lexer(#[from] LexerError),
 }
 
@@ -17,63 +18,262 @@ pub type Span = Range<usize>;
 
 #[derive(Error, Debug)]
 pub enum ParserError {
-    #[error("expected {}, found {found:?} at {}..{}", any_of(.expected), .span.start, .span.end)]
+    #[error("{}", expected_found(.expected, .found, .slice.as_deref()))]
     ExpectedToken {
         expected: Vec<Token>,
         found: Token,
         span: Span,
+        /// The source of `found`, see [`ParserError::with_source`]
+        slice: Option<String>,
     },
-    #[error("unexpected end of file at {}..{}{}", .span.start, .span.end, .expected.as_deref().map_or_else(String::new, |expected| format!(", expected {}", any_of(expected))))]
+    #[error("unexpected end of file{}", .expected.as_deref().map_or_else(String::new, |expected| format!(", expected {}", any_of(expected))))]
     UnexpectedEOF {
         expected: Option<Vec<Token>>,
         span: Span,
     },
-    #[error("expected end of file at {}..{}, found {found:?}", .span.start, .span.end)]
-    ExpectedEOF { found: Token, span: Range<usize> },
-
-    #[error("filtering {}, at {}..{}, but got {last_token:?}", .label.0, .span.start, .span.end)]
-    Filtering {
-        label: Filtering,
+    #[error("expected end of file, found {}", describe(.found, .slice.as_deref()))]
+    ExpectedEOF {
+        found: Token,
         span: Range<usize>,
-        last_token: Option<Token>,
+        /// The source of `found`, see [`ParserError::with_source`]
+        slice: Option<String>,
+    },
+
+    #[error("duplicate attribute `{name}`")]
+    DuplicateAttribute {
+        name: String,
+        span: Span,
+        first: Span,
+    },
+    #[error("conflicting ids `{first_id}` and `{id}`")]
+    ConflictingId {
+        id: String,
+        span: Span,
+        first_id: String,
+        first: Span,
+    },
+    #[error("unclosed `#[`, expected a `]` before the end of the line")]
+    UnclosedInterpolation { span: Span },
+    #[error("self-closing element `{name}` can't have any content")]
+    SelfClosingWithContent {
+        name: String,
+        span: Span,
+        slash: Span,
     },
+    #[error("unknown escape `\\{escape}`")]
+    UnknownEscape { escape: char, span: Span },
+    #[error("comments can't contain `--`, or start with `>` or `->`, or end with `-`")]
+    UnsafeComment { span: Span },
+    #[error("expected a tag after `:`")]
+    ExpectedTag { span: Span },
+    #[error("void element `{name}` can't have any content")]
+    VoidWithContent { name: String, span: Span },
+    #[error("unclosed `(`, expected a `)` to end the attributes")]
+    UnclosedParen { span: Span },
+    #[error("`{name}` attribute needs an escaped value, like `{name}=\"...\"`")]
+    UnmergeableAttribute { name: String, span: Span },
+}
+
+impl ParserError {
+    /// Adds the part of `source` that was found instead of what was expected, for tokens like
+    /// text that can be anything, so the message can show it
+    pub fn with_source(mut self, source: &str) -> Self {
+        if let ParserError::ExpectedToken {
+            found: Token::Text | Token::Error,
+            span,
+            slice,
+            ..
+        }
+        | ParserError::ExpectedEOF {
+            found: Token::Text | Token::Error,
+            span,
+            slice,
+        } = &mut self
+        {
+            *slice = source.get(span.clone()).map(str::to_owned);
+        }
+        self
+    }
+
+    /// The stable code of this kind of error, see `purgs explain`
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParserError::ExpectedToken { .. } => "P0001",
+            ParserError::UnexpectedEOF { .. } => "P0002",
+            ParserError::ExpectedEOF { .. } => "P0003",
+            ParserError::DuplicateAttribute { .. } => "P0005",
+            ParserError::ConflictingId { .. } => "P0006",
+            ParserError::UnclosedInterpolation { .. } => "P0008",
+            ParserError::SelfClosingWithContent { .. } | ParserError::VoidWithContent { .. } => {
+                "P0009"
+            }
+            ParserError::UnknownEscape { .. } => "P0012",
+            ParserError::UnsafeComment { .. } => "P0013",
+            ParserError::ExpectedTag { .. } => "P0014",
+            ParserError::UnclosedParen { .. } => "P0015",
+            ParserError::UnmergeableAttribute { .. } => "P0017",
+        }
+    }
+
+    /// The part of the source the error is about
+    pub fn span(&self) -> Span {
+        match self {
+            ParserError::ExpectedToken { span, .. }
+            | ParserError::UnexpectedEOF { span, .. }
+            | ParserError::ExpectedEOF { span, .. }
+            | ParserError::DuplicateAttribute { span, .. }
+            | ParserError::ConflictingId { span, .. }
+            | ParserError::UnclosedInterpolation { span }
+            | ParserError::SelfClosingWithContent { span, .. }
+            | ParserError::UnknownEscape { span, .. }
+            | ParserError::UnsafeComment { span }
+            | ParserError::ExpectedTag { span }
+            | ParserError::VoidWithContent { span, .. }
+            | ParserError::UnclosedParen { span }
+            | ParserError::UnmergeableAttribute { span, .. } => span.clone(),
+        }
+    }
+
+    /// Another part of the source that helps explain the error, with a note about it
+    pub fn related(&self) -> Option<(Span, &'static str)> {
+        match self {
+            ParserError::DuplicateAttribute { first, .. } => {
+                Some((first.clone(), "first set here"))
+            }
+            ParserError::ConflictingId { first, .. } => Some((first.clone(), "first set here")),
+            ParserError::SelfClosingWithContent { slash, .. } => {
+                Some((slash.clone(), "closed by this `/`"))
+            }
+            _ => None,
+        }
+    }
 }
 
 #[derive(Error, Debug)]
-#[error("lexer error at {}..{}", .0.start, .0.end)]
-pub struct LexerError(pub Span);
+pub enum LexerError {
+    #[error("unrecognized input")]
+    Unrecognized { span: Span },
+    #[error("indentation of {width} spaces isn't a multiple of {unit}")]
+    Indentation {
+        width: usize,
+        unit: usize,
+        span: Span,
+    },
+    #[error("this line is indented {levels} levels deeper than the line before it")]
+    OverIndented { levels: usize, span: Span },
+    #[error("this line is indented with {found}, but the file is indented with {expected}")]
+    MixedIndentation {
+        found: &'static str,
+        expected: &'static str,
+        span: Span,
+        /// The first indented line, which decided how the file is indented
+        first: Span,
+    },
+}
+
+impl LexerError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            LexerError::Unrecognized { .. } => "P0007",
+            LexerError::Indentation { .. } => "P0010",
+            LexerError::MixedIndentation { .. } => "P0011",
+            LexerError::OverIndented { .. } => "P0016",
+        }
+    }
 
-pub fn any_of<T: Debug>(things: &[T]) -> String {
+    pub fn span(&self) -> Span {
+        match self {
+            LexerError::Unrecognized { span }
+            | LexerError::Indentation { span, .. }
+            | LexerError::OverIndented { span, .. }
+            | LexerError::MixedIndentation { span, .. } => span.clone(),
+        }
+    }
+
+    pub fn related(&self) -> Option<(Span, &'static str)> {
+        match self {
+            // a line that mixes tabs and spaces by itself doesn't need to point at itself again
+            LexerError::MixedIndentation { first, span, .. } if first != span => {
+                Some((first.clone(), "first indented here"))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Parser(error) => error.code(),
+            Error::Lexer(error) => error.code(),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Error::Parser(error) => error.span(),
+            Error::Lexer(error) => error.span(),
+        }
+    }
+
+    pub fn related(&self) -> Option<(Span, &'static str)> {
+        match self {
+            Error::Parser(error) => error.related(),
+            Error::Lexer(error) => error.related(),
+        }
+    }
+
+    /// Shows the error with the source it points into, see [`Diagnostic`]
+    pub fn diagnostic<'a>(&'a self, file_name: &'a str, source: &'a str) -> Diagnostic<'a> {
+        Diagnostic::new(self, file_name, source)
+    }
+}
+
+fn expected_found(expected: &[Token], found: &Token, slice: Option<&str>) -> String {
+    let found = describe(found, slice);
+    if expected.is_empty() {
+        // `select!` doesn't say what it was looking for
+        format!("unexpected {found}")
+    } else {
+        format!("expected {}, found {found}", any_of(expected))
+    }
+}
+
+/// `text `foo`` when the source of the token is known, just `text` otherwise
+fn describe(token: &Token, slice: Option<&str>) -> String {
+    match slice {
+        Some(slice) => format!("{token} `{slice}`"),
+        None => token.to_string(),
+    }
+}
+
+/// `a`, `a or b` or `one of a, b or c`
+pub fn any_of<T: Display>(things: &[T]) -> String {
     match things {
-        [el] => format!("{el:?}"),
-        elements => format!("any of {elements:?}"),
+        [] => "anything".to_string(),
+        [el] => el.to_string(),
+        [first, second] => format!("{first} or {second}"),
+        [rest @ .., last] => format!("one of {} or {last}", rest.iter().join(", ")),
     }
 }
 
 impl<I: InputType<Token = Token, Span = Range<usize>>> aott::error::Error<I> for ParserError {
     fn expected_eof_found(span: I::Span, found: Token) -> Self {
-        Self::ExpectedEOF { found, span }
+        Self::ExpectedEOF {
+            found,
+            span,
+            slice: None,
+        }
     }
     fn expected_token_found(span: I::Span, expected: Vec<Token>, found: Token) -> Self {
         Self::ExpectedToken {
             span,
             expected,
             found,
+            slice: None,
         }
     }
     fn unexpected_eof(span: I::Span, expected: Option<Vec<Token>>) -> Self {
         Self::UnexpectedEOF { span, expected }
     }
 }
-
-impl<I: InputType<Token = Token, Span = Range<usize>>> aott::error::LabelError<I, Filtering>
-    for ParserError
-{
-    fn from_label(span: I::Span, label: Filtering, last_token: Option<Token>) -> Self {
-        Self::Filtering {
-            span,
-            label,
-            last_token,
-        }
-    }
-}
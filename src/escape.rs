@@ -0,0 +1,64 @@
+use std::borrow::Cow;
+use std::fmt;
+
+/// Escapes `text` so it can be placed between tags.
+pub fn escape_text(text: &str) -> Cow<'_, str> {
+    escape(text, is_text_special)
+}
+
+/// Escapes `value` so it can be placed inside a double- or single-quoted attribute value.
+pub fn escape_attr(value: &str) -> Cow<'_, str> {
+    escape(value, is_attr_special)
+}
+
+/// Like [`escape_text`], but writes straight into `out`.
+pub fn write_escaped_text(out: &mut impl fmt::Write, text: &str) -> fmt::Result {
+    write_escaped(out, text, is_text_special)
+}
+
+/// Like [`escape_attr`], but writes straight into `out`.
+pub fn write_escaped_attr(out: &mut impl fmt::Write, value: &str) -> fmt::Result {
+    write_escaped(out, value, is_attr_special)
+}
+
+fn is_text_special(c: char) -> bool {
+    matches!(c, '&' | '<' | '>')
+}
+
+fn is_attr_special(c: char) -> bool {
+    matches!(c, '&' | '<' | '>' | '"' | '\'')
+}
+
+fn escape(input: &str, needs_escape: fn(char) -> bool) -> Cow<'_, str> {
+    // most strings don't need escaping at all, so don't allocate for them
+    if !input.contains(needs_escape) {
+        return Cow::Borrowed(input);
+    }
+
+    let mut escaped = String::with_capacity(input.len() + 8);
+    write_escaped(&mut escaped, input, needs_escape).expect("writing to a String can't fail");
+    Cow::Owned(escaped)
+}
+
+fn write_escaped(
+    out: &mut impl fmt::Write,
+    input: &str,
+    needs_escape: fn(char) -> bool,
+) -> fmt::Result {
+    let mut rest = input;
+
+    while let Some(idx) = rest.find(needs_escape) {
+        out.write_str(&rest[..idx])?;
+        // all the special characters are ASCII, so they're one byte long
+        out.write_str(match rest.as_bytes()[idx] {
+            b'&' => "&amp;",
+            b'<' => "&lt;",
+            b'>' => "&gt;",
+            b'"' => "&quot;",
+            _ => "&#39;",
+        })?;
+        rest = &rest[idx + 1..];
+    }
+
+    out.write_str(rest)
+}
@@ -0,0 +1,278 @@
+//! Longer explanations of the error codes, shown by `purgs explain <code>`
+
+/// The explanation for `code` (like `P0005`), if there is such an error
+pub fn explain(code: &str) -> Option<&'static str> {
+    let code = code.to_ascii_uppercase();
+    EXPLANATIONS
+        .iter()
+        .find(|(known, _)| *known == code)
+        .map(|(_, explanation)| *explanation)
+}
+
+const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "P0001",
+        r#"Something was written where it isn't allowed.
+
+Every line is a tag with its `#id`, `.classes` and `(attributes)`, followed by
+a space and its text. Anything else in between doesn't fit.
+
+Wrong:
+
+    a(href="/")home
+
+Right:
+
+    a(href="/") home
+"#,
+    ),
+    (
+        "P0002",
+        r#"The file ended in the middle of something, usually an attribute list or a
+quoted value that was never closed.
+
+Wrong:
+
+    a(href="/
+
+Right:
+
+    a(href="/") home
+"#,
+    ),
+    (
+        "P0003",
+        r#"There is more input after the end of the document that couldn't be parsed.
+
+Lines that are indented wrong, like an indented first line, are reported on
+their own (see P0001, P0010 and P0016) and parsing goes on after them, so this
+only comes up if the parser couldn't find its way back to the next line.
+"#,
+    ),
+    (
+        "P0005",
+        r#"An attribute was set more than once on the same tag. Only one of the values
+could end up in the output, so this is an error instead of picking one.
+
+Wrong:
+
+    a(href="/", href="/home") home
+
+Right:
+
+    a(href="/home") home
+
+`class` is the exception: every class from `.class` shorthands and `class`
+attributes is kept.
+"#,
+    ),
+    (
+        "P0006",
+        r#"A tag was given two different ids, one with `#id` and one with an `id`
+attribute. An element can only have one id.
+
+Wrong:
+
+    div#main(id="content")
+
+Right:
+
+    div#main
+
+Setting the same id both ways is allowed.
+"#,
+    ),
+    (
+        "P0007",
+        r#"The file contains characters that aren't part of the template syntax outside
+of text, like `$` or `~` in a tag name.
+
+Wrong:
+
+    $div text
+
+Right:
+
+    div text
+"#,
+    ),
+    (
+        "P0008",
+        r#"A `#[` in text starts a tag that's part of the text, and it has to be closed
+with a `]` on the same line.
+
+Wrong:
+
+    p This is #[strong important text
+
+Right:
+
+    p This is #[strong important] text
+
+To write a literal `#[`, put a backslash in front of it: `\#[`.
+"#,
+    ),
+    (
+        "P0009",
+        r#"A tag that ends in `/` is closed right away (`<path/>`), so it can't have text
+after it or children indented below it. The same goes for void elements like `br`,
+`img` or `input`, which HTML doesn't allow any content in.
+
+Wrong:
+
+    path(d="M 0 0 L 10 10")/ line
+    g/
+        circle(r="5")/
+    p a #[br b]
+
+Right:
+
+    path(d="M 0 0 L 10 10")/
+    g
+        circle(r="5")/
+    p a #[br] b
+"#,
+    ),
+    (
+        "P0010",
+        r#"A line is indented by a number of spaces that isn't a whole number of levels.
+The width of one level is taken from the first indented line of the file
+(or from `--indent-width`), and every line has to stick to it.
+
+Wrong:
+
+    ul
+      li one
+       li two
+
+Right:
+
+    ul
+      li one
+      li two
+"#,
+    ),
+    (
+        "P0011",
+        r#"A line is indented with tabs in a file that's indented with spaces, or the other
+way around. How wide a tab is depends on the editor, so there's no telling which
+level the line is meant to be on.
+
+Wrong (`→` is a tab):
+
+    ul
+        li one
+    →   li two
+
+Right:
+
+    ul
+        li one
+        li two
+
+`purgs --normalize <file>` prints the file with every line indented like the first
+indented line, counting a tab as one level.
+"#,
+    ),
+    (
+        "P0012",
+        r#"A backslash in a quoted attribute value escapes the character after it, and only
+`\"`, `\'` and `\\` are escapes. Anything else after a backslash is an error, so
+write `\\` for a backslash of its own.
+
+Wrong:
+
+    a(title="C:\new")
+
+Right:
+
+    a(title="C:\\new")
+"#,
+    ),
+    (
+        "P0013",
+        r#"A `//` comment is written out as an HTML comment, `<!-- ... -->`. HTML ends a
+comment at the first `-->`, so a comment that contains `--`, starts with `>` or
+`->`, or ends with `-` could end early and let the rest through as markup.
+
+Wrong:
+
+    // old version -- remove later
+
+Right:
+
+    // old version, remove later
+
+A `//-` comment isn't written out, so it can contain anything.
+"#,
+    ),
+    (
+        "P0014",
+        r#"A `:` after a tag nests the tag after it on the same line, so there has to be
+one. To put the child on the next line instead, leave out the `:` and indent it.
+
+Wrong:
+
+    li:
+        a Home
+
+Right:
+
+    li: a Home
+"#,
+    ),
+    (
+        "P0015",
+        r#"The attributes of a tag have to be closed with a `)`. They can span several
+lines, as long as those lines are indented further than the tag or start with the
+`)`, so a line back at the tag's indentation ends them.
+
+Wrong:
+
+    input(type="text"
+    p next
+
+Right:
+
+    input(type="text")
+    p next
+"#,
+    ),
+    (
+        "P0016",
+        r#"A line is indented more than one level deeper than the line before it. Only
+one level at a time makes the line a child of the one before, so there's no telling
+what the levels in between would be.
+
+Wrong:
+
+    ul
+        li
+                a one
+
+Right:
+
+    ul
+        li
+            a one
+"#,
+    ),
+    (
+        "P0017",
+        r#"`class` and `id` attributes are merged with the `.class` and `#id` shorthands,
+so they need a value, and it's always escaped like the shorthands are. Leaving
+the value out or writing it unescaped with `!=` would end up as a second `class`
+or `id` attribute, or lose the `!`.
+
+Wrong:
+
+    div.box(class)
+    div#main(id!="main")
+
+Right:
+
+    div.box
+    div#main(id="main")
+"#,
+    ),
+];
@@ -55,7 +55,7 @@ where
                     return None;
                 }
             }
-        } else if let Some(r) = self.buf.get(0) {
+        } else if let Some(r) = self.buf.front() {
             if !accept(r) {
                 return None;
             }
@@ -1,22 +1,29 @@
+use std::fmt;
 use std::ops::Range;
 use tracing::*;
 
 use aott::derive::IntoString;
-use logos::Logos;
+use logos::{Logos, SpannedIter};
 
+use std::cell::RefCell;
 use std::cmp::Ordering;
+use std::collections::HashMap;
 
+use crate::errors::LexerError;
 use crate::iter::NanoPeek;
 
 #[derive(Clone, Logos, Debug, PartialEq, IntoString)]
 pub enum SmallToken {
-    #[regex(r"\t+|([ ]{4})+")]
-    Indent,
+    /// Indentation at the start of a line, or whitespace anywhere else
+    #[regex(r"[ \t]+")]
+    Spaces,
 
-    #[regex(r"\n")]
+    /// `\n` or `\r\n`
+    #[regex(r"\r?\n")]
     Newline,
 
-    #[regex(r"[a-zA-Z0-9-]+")]
+    /// Words in any script, so classes, ids and attribute names aren't limited to ASCII
+    #[regex(r"[\p{L}\p{M}\p{N}_-]+")]
     Text,
 
     #[regex(r#""|'"#)]
@@ -34,31 +41,90 @@ pub enum SmallToken {
     #[token(")")]
     CloseParen,
 
+    #[token("[")]
+    OpenBracket,
+
+    #[token("]")]
+    CloseBracket,
+
     #[token("=")]
     Equals,
 
+    #[token(":")]
+    Colon,
+
+    #[token("@")]
+    At,
+
     #[token(",")]
     Comma,
 
-    #[token(" ")]
-    WhiteSpace,
+    #[token("!")]
+    Bang,
+
+    #[token("\\")]
+    Backslash,
+
+    #[token("/")]
+    Slash,
+
+    #[token("|")]
+    Pipe,
 }
 
-#[derive(Clone, Logos, Debug, PartialEq)]
+/// Tokens don't carry their text, the parser slices it out of the source with the token's span
+#[derive(Clone, Copy, Logos, Debug, PartialEq, Eq)]
 pub enum Token {
     Indent,
     Dedent,
     Newline,
-    Text(String),
-    Quote(String),
+    Text,
+    Quote(char),
     Dot,
     Hash,
     OpenParen,
     CloseParen,
+    OpenBracket,
+    CloseBracket,
     Equals,
+    Colon,
+    At,
     Comma,
+    Bang,
+    Backslash,
+    Slash,
+    Pipe,
     WhiteSpace,
-    Error(String),
+    Error,
+}
+
+/// How tokens are named in error messages
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Indent => f.write_str("an indent"),
+            Token::Dedent => f.write_str("a dedent"),
+            Token::Newline => f.write_str("a line break"),
+            Token::Text => f.write_str("text"),
+            Token::Quote(quote) => write!(f, "`{quote}`"),
+            Token::Dot => f.write_str("`.`"),
+            Token::Hash => f.write_str("`#`"),
+            Token::OpenParen => f.write_str("`(`"),
+            Token::CloseParen => f.write_str("`)`"),
+            Token::OpenBracket => f.write_str("`[`"),
+            Token::CloseBracket => f.write_str("`]`"),
+            Token::Equals => f.write_str("`=`"),
+            Token::Colon => f.write_str("`:`"),
+            Token::At => f.write_str("`@`"),
+            Token::Comma => f.write_str("`,`"),
+            Token::Bang => f.write_str("`!`"),
+            Token::Backslash => f.write_str("`\\`"),
+            Token::Slash => f.write_str("`/`"),
+            Token::Pipe => f.write_str("`|`"),
+            Token::WhiteSpace => f.write_str("a space"),
+            Token::Error => f.write_str("unrecognized input"),
+        }
+    }
 }
 
 /*
@@ -68,68 +134,521 @@ pub struct Token {
     pub slice: String,
 }*/
 
-pub struct Lexer {
-    // HACK: We need 'static here to get rid of lifetimes in the parsers
-    logos: NanoPeek<logos::Lexer<'static, SmallToken>>,
+/// The tokens of a source file, lexed as the parser asks for them
+pub struct Lexer<'a> {
+    layout: Layout<'a>,
+    errors: &'a RefCell<Vec<LexerError>>,
+}
+
+impl<'a> Lexer<'a> {
+    /// `indent_width` is the number of spaces per level of indentation, `None` takes it from the
+    /// first line that's indented with spaces. Problems with the layout of the source, like
+    /// indentation that doesn't match the rest, are added to `errors` as they're found.
+    pub fn new(
+        input: &'a str,
+        indent_width: Option<usize>,
+        errors: &'a RefCell<Vec<LexerError>>,
+    ) -> Self {
+        Self {
+            layout: Layout::new(input, indent_width),
+            errors,
+        }
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = (Token, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.layout.next();
+        self.errors.borrow_mut().append(&mut self.layout.errors);
+        token
+    }
+}
+
+/// Rewrites the indentation of every line of `input` to use whatever the first indented line
+/// uses, with a tab standing for one level. `indent_width` is the number of spaces per level like
+/// for [`Lexer::new`].
+///
+/// Lines of text blocks and multi-line attribute lists only get the indentation of the line they
+/// belong to rewritten, whatever they're indented by past that is part of their text.
+pub fn normalize_indentation(input: &str, indent_width: Option<usize>) -> String {
+    let mut layout = Layout::new(input, indent_width);
+    layout.inside = Some(vec![]);
+    layout.by_ref().for_each(drop);
+
+    let tabs = matches!(layout.style, Some(('\t', _)));
+    let unit = layout.unit.unwrap_or(4);
+    let inside: HashMap<usize, usize> = layout.inside.unwrap_or_default().into_iter().collect();
+    let indentation = |line: &str| line.len() - line.trim_start_matches([' ', '\t']).len();
+
+    // the new indentation of every line, by where it starts
+    let mut rewritten = HashMap::new();
+    let mut output = String::with_capacity(input.len());
+    let mut start = 0;
+    for line in input.split_inclusive('\n') {
+        let (indent, rest) = line.split_at(indentation(line));
+
+        let new_indent = match inside.get(&start) {
+            Some(&owner) => {
+                let old_owner = &input[owner..owner + indentation(&input[owner..])];
+                match (rewritten.get(&owner), indent.strip_prefix(old_owner)) {
+                    (Some(new_owner), Some(own)) => format!("{new_owner}{own}"),
+                    _ => indent.to_owned(),
+                }
+            }
+            // blank lines don't belong to any level
+            None if rest.trim().is_empty() => indent.to_owned(),
+            None => {
+                let spaces = indent.bytes().filter(|&byte| byte == b' ').count();
+                let width = (indent.len() - spaces) * unit + spaces;
+                if tabs {
+                    "\t".repeat(width / unit) + &" ".repeat(width % unit)
+                } else {
+                    " ".repeat(width)
+                }
+            }
+        };
+
+        output.push_str(&new_indent);
+        output.push_str(rest);
+        rewritten.insert(start, new_indent);
+        start += line.len();
+    }
+
+    output
+}
+
+/// Turns logos' tokens into [`Token`]s, working out the indents and dedents from the
+/// indentation at the start of each line
+struct Layout<'a> {
+    source: &'a str,
+    logos: NanoPeek<SpannedIter<'a, SmallToken>>,
+    /// The level of indentation of the current line
     indent: usize,
+    /// The levels of the indented blocks that are open, one per indent that wasn't dedented yet
+    levels: Vec<usize>,
     just_dedented: bool,
     dedents_left: usize,
+    /// Where the last token from the source ended, which is where the made-up tokens are placed
+    end: usize,
+    /// The last token that was emitted
+    previous: Option<Token>,
+    /// Whether the line is still at the tag (`a#id.class(...)`), before any text
+    in_head: bool,
+    /// How deep into a tag's `(attributes)` the lexer is. Line breaks and indentation in there
+    /// are just whitespace, so attribute lists can span multiple lines.
+    parens: usize,
+    /// The indentation width of the line the attribute list started on. A line that's indented
+    /// no further than that ends an unclosed list, unless it starts with the `)`.
+    parens_width: usize,
+    /// The quote of the attribute value the lexer is in, so parentheses in values are ignored
+    quote: Option<char>,
+    /// Whether the last token was a backslash that escapes the next one in an attribute value
+    escaped: bool,
+    /// The indentation width and level of a `tag.` or `//` line, whose indented block is text
+    /// that can't contain attribute lists and can be indented any way it likes
+    text_block: Option<(usize, usize)>,
+    /// Where the line that started the current text block or attribute list starts
+    owner: usize,
+    /// The lines of text blocks and attribute lists by where they start, along with where the
+    /// line they belong to starts. Only [`normalize_indentation`] needs these.
+    inside: Option<Vec<(usize, usize)>>,
+    /// The number of spaces per level of indentation
+    unit: Option<usize>,
+    /// What the first indented line was indented with, and where it was
+    style: Option<(char, Range<usize>)>,
+    /// Whether a line that doesn't stick to `style` was found already. Only the first one is
+    /// reported, every line after it would most likely be wrong too.
+    mixed: bool,
+    /// How wide the indentation of the current line is
+    line_width: usize,
+    errors: Vec<LexerError>,
 }
 
-impl Iterator for Lexer {
+impl<'a> Layout<'a> {
+    /// `unit` is the number of spaces per level, `None` takes it from the first line that's
+    /// indented with spaces
+    fn new(source: &'a str, unit: Option<usize>) -> Self {
+        let mut logos = SmallToken::lexer(source);
+        // a byte order mark isn't part of the template, but spans still count it
+        if source.starts_with('\u{feff}') {
+            logos.bump('\u{feff}'.len_utf8());
+        }
+
+        Self {
+            source,
+            logos: NanoPeek::new(logos.spanned()),
+            indent: 0,
+            levels: vec![],
+            just_dedented: false,
+            dedents_left: 0,
+            end: 0,
+            previous: None,
+            in_head: true,
+            parens: 0,
+            parens_width: 0,
+            quote: None,
+            escaped: false,
+            text_block: None,
+            owner: 0,
+            inside: None,
+            // no indentation at all can't be a level
+            unit: unit.filter(|&unit| unit > 0),
+            style: None,
+            mixed: false,
+            line_width: 0,
+            errors: vec![],
+        }
+    }
+
+    fn at_line_start(&self) -> bool {
+        matches!(
+            self.previous,
+            None | Some(Token::Newline | Token::Indent | Token::Dedent)
+        )
+    }
+
+    /// Whether the current line is one of a text block's, not the line that starts it
+    fn in_text_block(&self) -> bool {
+        self.text_block
+            .is_some_and(|(width, _)| self.line_width > width)
+    }
+
+    /// Where the line that `offset` is on starts
+    fn line_start(&self, offset: usize) -> usize {
+        self.source[..offset]
+            .rfind('\n')
+            .map_or(0, |newline| newline + 1)
+    }
+
+    /// Whether nothing but the line break (if any) is left of the line at `offset`
+    fn at_line_end(&self, offset: usize) -> bool {
+        let rest = &self.source[offset..];
+        rest.is_empty() || rest.starts_with('\n') || rest.starts_with("\r\n")
+    }
+
+    /// Skips the lines coming up that are empty or only contain whitespace, so they neither end
+    /// blocks nor count as lines of their own
+    fn skip_blank_lines(&mut self) {
+        loop {
+            self.logos.reset_peek();
+            let blank = match self.logos.peek() {
+                Some((Ok(SmallToken::Newline), _)) => true,
+                Some((Ok(SmallToken::Spaces), span)) => {
+                    let end = span.end;
+                    self.at_line_end(end)
+                }
+                _ => false,
+            };
+            if !blank {
+                break;
+            }
+            self.logos.next();
+        }
+        self.logos.reset_peek();
+    }
+
+    /// The level of indentation at the start of a line
+    fn indent_level(&mut self, span: &Range<usize>) -> usize {
+        let slice = &self.source[span.clone()];
+        self.line_width = slice.len();
+
+        // lines in a text block are all one level deeper than the line that started it, however
+        // they are indented
+        if let Some((width, level)) = self.text_block {
+            if slice.len() > width {
+                if let Some(inside) = &mut self.inside {
+                    inside.push((span.start, self.owner));
+                }
+                self.in_head = false;
+                return level + 1;
+            }
+            self.text_block = None;
+        }
+
+        self.check_style(slice, span);
+
+        // a tab is always one level, spaces are however many make up one
+        let tabs = slice.bytes().filter(|&byte| byte == b'\t').count();
+        let spaces = slice.len() - tabs;
+        if spaces == 0 {
+            return tabs;
+        }
+
+        // only lines that are all spaces decide the unit and have to stick to it, a line that
+        // mixes in tabs is reported as such already
+        if tabs > 0 {
+            return tabs + spaces / self.unit.unwrap_or(spaces);
+        }
+        let unit = *self.unit.get_or_insert(spaces);
+        if spaces % unit != 0 {
+            self.errors.push(LexerError::Indentation {
+                width: spaces,
+                unit,
+                span: span.clone(),
+            });
+        }
+
+        spaces / unit
+    }
+
+    /// Reports the first line that's indented with something else than the first indented line
+    fn check_style(&mut self, slice: &str, span: &Range<usize>) {
+        let (style, first) = self
+            .style
+            .get_or_insert_with(|| (slice.chars().next().unwrap_or(' '), span.clone()))
+            .clone();
+        if self.mixed || slice.chars().all(|char| char == style) {
+            return;
+        }
+
+        self.mixed = true;
+        let name = |char| if char == '\t' { "tabs" } else { "spaces" };
+        let found = if slice.contains(style) {
+            "tabs and spaces"
+        } else {
+            name(if style == '\t' { ' ' } else { '\t' })
+        };
+        self.errors.push(LexerError::MixedIndentation {
+            found,
+            expected: name(style),
+            span: span.clone(),
+            first,
+        });
+    }
+
+    /// Whether the line after the line break coming up ends an attribute list that was never
+    /// closed, by going back to the indentation of its tag
+    fn ends_attributes(&mut self) -> bool {
+        self.logos.reset_peek();
+        // blank lines don't count, like everywhere else
+        let (width, next) = loop {
+            match self.logos.peek() {
+                Some((Ok(SmallToken::Newline), _)) => {}
+                Some((Ok(SmallToken::Spaces), span)) => {
+                    let width = span.len();
+                    match self.logos.peek() {
+                        Some((Ok(SmallToken::Newline), _)) => {}
+                        next => break (width, next.map(|(token, _)| token.clone())),
+                    }
+                }
+                next => break (0, next.map(|(token, _)| token.clone())),
+            }
+        };
+        self.logos.reset_peek();
+
+        width <= self.parens_width && next != Some(Ok(SmallToken::CloseParen))
+    }
+
+    /// Tokens inside of `(attributes)`, where lines don't matter
+    fn attribute_token(&mut self, token: SmallToken, span: &Range<usize>) -> Token {
+        let escaped = std::mem::take(&mut self.escaped);
+
+        match token {
+            SmallToken::Spaces if self.line_start(span.start) == span.start => {
+                if let Some(inside) = &mut self.inside {
+                    inside.push((span.start, self.owner));
+                }
+                Token::WhiteSpace
+            }
+            SmallToken::Newline | SmallToken::Spaces => Token::WhiteSpace,
+            SmallToken::Quote => {
+                let quote = self.quote_char(span);
+                match self.quote {
+                    None => self.quote = Some(quote),
+                    Some(open) if open == quote && !escaped => self.quote = None,
+                    Some(_) => {}
+                }
+                Token::Quote(quote)
+            }
+            // a backslash that's escaped itself doesn't escape what's after it
+            SmallToken::Backslash => {
+                self.escaped = self.quote.is_some() && !escaped;
+                Token::Backslash
+            }
+            SmallToken::OpenParen if self.quote.is_none() => {
+                self.parens += 1;
+                Token::OpenParen
+            }
+            SmallToken::CloseParen if self.quote.is_none() => {
+                self.parens = self.parens.saturating_sub(1);
+                Token::CloseParen
+            }
+            token => self.token(token, span),
+        }
+    }
+
+    /// The token for everything that doesn't depend on where in the file it is
+    fn token(&self, token: SmallToken, span: &Range<usize>) -> Token {
+        match token {
+            SmallToken::Dot => Token::Dot,
+            SmallToken::Hash => Token::Hash,
+            SmallToken::OpenParen => Token::OpenParen,
+            SmallToken::CloseParen => Token::CloseParen,
+            SmallToken::OpenBracket => Token::OpenBracket,
+            SmallToken::CloseBracket => Token::CloseBracket,
+            SmallToken::Equals => Token::Equals,
+            SmallToken::Colon => Token::Colon,
+            SmallToken::At => Token::At,
+            SmallToken::Comma => Token::Comma,
+            SmallToken::Bang => Token::Bang,
+            SmallToken::Backslash => Token::Backslash,
+            SmallToken::Slash => Token::Slash,
+            SmallToken::Pipe => Token::Pipe,
+            SmallToken::Quote => Token::Quote(self.quote_char(span)),
+            SmallToken::Text => Token::Text,
+            SmallToken::Newline => Token::Newline,
+            SmallToken::Spaces => Token::WhiteSpace,
+        }
+    }
+
+    fn quote_char(&self, span: &Range<usize>) -> char {
+        if &self.source[span.clone()] == "'" {
+            '\''
+        } else {
+            '"'
+        }
+    }
+}
+
+impl Iterator for Layout<'_> {
     type Item = (Token, Range<usize>);
 
-    #[instrument(skip(self), level = "trace", ret, fields(self.dedents_left, self.indent, self.just_dedented, slice = self.logos.inner.slice()))]
+    #[instrument(skip(self), level = "trace", ret, fields(self.dedents_left, self.indent, self.just_dedented))]
     fn next(&mut self) -> Option<Self::Item> {
         if self.dedents_left > 0 {
             self.dedents_left -= 1;
             if self.dedents_left == 0 {
                 self.just_dedented = true;
             }
-            return Some((Token::Dedent, self.logos.inner.span()));
+            self.previous = Some(Token::Dedent);
+            return Some((Token::Dedent, self.end..self.end));
         }
         // just_dedented makes Newline->Dedent into Newline->Dedent->Newline so the parser doesn't suffer so put it after other checks that emit a dedent
         if self.just_dedented {
             self.just_dedented = false;
-            return Some((Token::Newline, self.logos.inner.span()));
+            self.previous = Some(Token::Newline);
+            return Some((Token::Newline, self.end..self.end));
         }
-        let Ok(token) = self.logos.next()? else {
-            return Some((
-                Token::Error(self.logos.inner.slice().to_owned()),
-                self.logos.inner.span(),
-            ));
+        if self.previous.is_none() {
+            self.skip_blank_lines();
+        }
+        let (token, span) = self.logos.next()?;
+        self.end = span.end;
+        let Ok(token) = token else {
+            // in text anything goes, it's only an error where a tag or attribute is expected
+            if self.in_head && self.quote.is_none() {
+                self.errors
+                    .push(LexerError::Unrecognized { span: span.clone() });
+            }
+            self.previous = Some(Token::Error);
+            return Some((Token::Error, span));
         };
 
+        // a line back at the tag's indentation is a new line, so an unclosed `(` can't swallow
+        // the rest of the file
+        if self.parens > 0 && token == SmallToken::Newline && self.ends_attributes() {
+            self.parens = 0;
+            self.quote = None;
+            self.escaped = false;
+        }
+
+        if self.parens > 0 {
+            let kind = self.attribute_token(token, &span);
+            self.previous = Some(kind);
+            return Some((kind, span));
+        }
+
         let kind = match token {
-            SmallToken::Dot => Token::Dot,
-            SmallToken::Hash => Token::Hash,
-            SmallToken::OpenParen => Token::OpenParen,
-            SmallToken::CloseParen => Token::CloseParen,
-            SmallToken::Equals => Token::Equals,
-            SmallToken::Comma => Token::Comma,
-            SmallToken::WhiteSpace => Token::WhiteSpace,
-            SmallToken::Quote => Token::Quote(self.logos.inner.slice().to_owned()),
-            SmallToken::Text => Token::Text(self.logos.inner.slice().to_owned()),
+            // only a `(` right after the tag starts attributes, not one in the text
+            SmallToken::OpenParen => {
+                if self.in_head {
+                    self.parens = 1;
+                    self.parens_width = self.line_width;
+                    self.owner = self.line_start(span.start);
+                }
+                Token::OpenParen
+            }
+            // trailing whitespace doesn't end up in the line's text, unless it's a line of a text
+            // block where it's part of the text
+            SmallToken::Spaces
+                if !self.at_line_start() && self.at_line_end(span.end) && !self.in_text_block() =>
+            {
+                return self.next();
+            }
+            SmallToken::Spaces if !self.at_line_start() => {
+                // `tag: child` keeps going with the child's tag
+                if self.previous != Some(Token::Colon) {
+                    self.in_head = false;
+                }
+                Token::WhiteSpace
+            }
+            SmallToken::Pipe => {
+                self.in_head = false;
+                Token::Pipe
+            }
+            // a `//` line inside a text block is part of its text, not a comment of its own
+            SmallToken::Slash => {
+                if self.at_line_start() && !self.in_text_block() {
+                    self.text_block = Some((self.line_width, self.indent));
+                    self.owner = self.line_start(span.start);
+                }
+                self.in_head = false;
+                Token::Slash
+            }
             SmallToken::Newline => {
-                if let Some(Ok(SmallToken::Indent)) = self.logos.peek() {
-                } else if self.indent > 0 {
-                    self.dedents_left = self.indent;
+                if self.in_head && self.previous == Some(Token::Dot) {
+                    self.text_block = Some((self.line_width, self.indent));
+                    self.owner = self.line_start(span.start);
+                }
+                self.in_head = true;
+                self.skip_blank_lines();
+
+                if let Some((Ok(SmallToken::Spaces), _)) = self.logos.peek() {
+                } else {
+                    self.text_block = None;
+                    self.line_width = 0;
+                    if !self.levels.is_empty() {
+                        self.dedents_left = self.levels.len();
+                        self.levels.clear();
+                        self.indent = 0;
+                    }
                 }
                 Token::Newline
             }
-            SmallToken::Indent => {
-                let slice = self.logos.inner.slice();
-                let indent_len = if slice.starts_with('\t') {
-                    slice.len()
-                } else {
-                    slice.len() / 4
-                };
+            SmallToken::Spaces => {
+                let indent_len = self.indent_level(&span);
+
+                // every block that's deeper than the line is closed, however many levels apart
+                // they are
+                let open = self
+                    .levels
+                    .iter()
+                    .filter(|&&open| open > indent_len)
+                    .count();
 
                 let token = match indent_len.cmp(&self.indent) {
-                    Ordering::Greater => Token::Indent,
-                    Ordering::Equal => return self.next(),
-                    Ordering::Less => {
-                        self.dedents_left = (self.indent - indent_len).saturating_sub(1);
+                    Ordering::Greater => {
+                        if indent_len > self.indent + 1 {
+                            self.errors.push(LexerError::OverIndented {
+                                levels: indent_len - self.indent,
+                                span: span.clone(),
+                            });
+                        }
+                        self.levels.push(indent_len);
+                        Token::Indent
+                    }
+                    // a line between two levels after one that was indented too far stays in the
+                    // block it's in
+                    Ordering::Equal | Ordering::Less if open == 0 => {
+                        self.indent = indent_len;
+                        self.previous = Some(Token::Indent);
+                        return self.next();
+                    }
+                    Ordering::Equal | Ordering::Less => {
+                        self.levels.truncate(self.levels.len() - open);
+                        self.dedents_left = open - 1;
                         self.just_dedented = true;
                         Token::Dedent
                     }
@@ -138,22 +657,10 @@ impl Iterator for Lexer {
                 self.indent = indent_len;
                 token
             }
+            token => self.token(token, &span),
         };
 
-        Some((kind, self.logos.inner.span()))
-    }
-}
-
-impl Lexer {
-    pub fn new(input: &str) -> Self {
-        Self {
-            // FIXME: We don't deallocate this but currently I don't care
-            logos: NanoPeek::new(SmallToken::lexer(Box::leak::<'static>(
-                input.to_owned().into_boxed_str(),
-            ))),
-            indent: 0,
-            just_dedented: false,
-            dedents_left: 0,
-        }
+        self.previous = Some(kind);
+        Some((kind, span))
     }
 }
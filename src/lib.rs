@@ -1,12 +1,17 @@
 use regex::Regex;
 use std::cmp::Ordering;
 pub mod ast;
+pub mod diagnostic;
 pub mod errors;
+pub mod escape;
+pub mod explain;
 pub mod iter;
 mod lexer;
 mod parser;
+pub mod render;
 
-pub use parser::parse;
+pub use lexer::normalize_indentation;
+pub use parser::{parse, parse_recovering, ParseOptions};
 
 const SELF_CLOSING_TAGS: [&str; 16] = [
     "area", "base", "br", "col", "embed", "hr", "img", "input", "keygen", "link", "menuitem",
@@ -125,59 +130,38 @@ fn historical(content: &str) -> String {
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
-
-    use crate::ast::Tag;
+    use crate::ast::{AttrValue, Attribute, Node, Spanned, Tag, Text};
+    use crate::render::{Mode, RenderOptions};
 
     fn check_str(input: &str) {
         println!("Testing input \"{input}\"");
         println!("Old: {}", crate::historical(input));
         println!(
             "New: {:?}",
-            crate::parse(input).unwrap_or_else(|error| {
-                match &error {
-                    crate::errors::Error::Parser(
-                        crate::errors::ParserError::Filtering { span, .. }
-                        | crate::errors::ParserError::ExpectedEOF { span, .. }
-                        | crate::errors::ParserError::ExpectedToken { span, .. }
-                        | crate::errors::ParserError::UnexpectedEOF { span, .. },
-                    ) => eprintln!(
-                        "at {span_start}..{span_end}, slice: \"{slice}\" (the span + 3 chars before it)",
-                        span_start = span.start,
-                        span_end = span.end,
-                        slice = &input[(span.start.saturating_sub(3))..(span.end)]
-                    ),
-                    _ => unreachable!(),
-                };
-
-                panic!("Error: {error}")
-            })
+            crate::parse(input)
+                .unwrap_or_else(|error| { panic!("{}", error.diagnostic("<input>", input)) })
         );
     }
 
-    fn test_str(input: &str, expected: Vec<Tag>) {
+    fn test_str(input: &str, expected: Vec<Node>) {
         println!("Testing input \"{input}\"");
-        let out = crate::parse(input).unwrap_or_else(|error| {
-                match &error {
-                    crate::errors::Error::Parser(
-                        crate::errors::ParserError::Filtering { span, .. }
-                        | crate::errors::ParserError::ExpectedEOF { span, .. }
-                        | crate::errors::ParserError::ExpectedToken { span, .. }
-                        | crate::errors::ParserError::UnexpectedEOF { span, .. },
-                    ) => eprintln!(
-                        "at {span_start}..{span_end}, slice: \"{slice}\" (the span + 3 chars before it)",
-                        span_start = span.start,
-                        span_end = span.end,
-                        slice = &input[(span.start.saturating_sub(3))..(span.end)]
-                    ),
-                    _ => unreachable!(),
-                };
-
-                panic!("Error: {error}")
-            });
+        let out = crate::parse(input)
+            .unwrap_or_else(|error| panic!("{}", error.diagnostic("<input>", input)));
         println!("Actual: {:?}", out);
 
-        assert_eq!(out, expected);
+        assert_eq!(out.nodes, expected);
+    }
+
+    fn render(input: &str) -> String {
+        render_with(input, &RenderOptions::default())
+    }
+
+    fn render_with(input: &str, options: &RenderOptions) -> String {
+        let document = crate::parse(input)
+            .unwrap_or_else(|error| panic!("{}", error.diagnostic("<input>", input)));
+        let mut html = String::new();
+        crate::render::render(&mut html, &document, options).unwrap();
+        html
     }
 
     #[test]
@@ -187,6 +171,823 @@ mod tests {
 
     #[test]
     fn multi_dedent() {
-        test_str("html\n\thead\n\t\tmeta(width=\"device-width=true\")\n\tbody\n\t\tdiv#content.hello Hello World\nanotertoplevelthinglolhaha", vec![Tag { name: "html".to_string(), attrs: HashMap::new(), id: None, classes: vec![], children: vec![Tag { name: "head".to_string(), attrs: HashMap::new(), id: None, classes: vec![], children: vec![Tag { name: "meta".to_string(), attrs: HashMap::from_iter([("width".to_string(), Some("device-width=true".to_string()))]), id: None, classes: vec![], children: vec![], content: None }], content: None }], content: None }, Tag { name: "anotertoplevelthinglolhaha".to_string(), attrs: HashMap::new(), id: None, classes: vec![], children: vec![], content: None }]);
+        test_str("html\n\thead\n\t\tmeta(width=\"device-width=true\")\n\tbody\n\t\tdiv#content.hello Hello World\nanotertoplevelthinglolhaha", vec![Node::Element(Tag { name: "html".to_string(), attrs: vec![], id: None, classes: vec![], children: vec![Node::Element(Tag { name: "head".to_string(), attrs: vec![], id: None, classes: vec![], children: vec![Node::Element(Tag { name: "meta".to_string(), attrs: vec![Attribute { name: "width".to_string(), value: Some(AttrValue::new("device-width=true")), span: 18..43 }], id: None, classes: vec![], children: vec![], self_closing: false, span: 13..44 })], self_closing: false, span: 6..44 }), Node::Element(Tag { name: "body".to_string(), attrs: vec![], id: None, classes: vec![], children: vec![Node::Element(Tag { name: "div".to_string(), attrs: vec![], id: Some(Spanned { value: "content".to_string(), span: 56..64 }), classes: vec![Spanned { value: "hello".to_string(), span: 64..70 }], children: vec![Node::Text(Text { text: "Hello World".to_string(), raw: false, span: 71..82 })], self_closing: false, span: 53..82 })], self_closing: false, span: 46..82 })], self_closing: false, span: 0..82 }), Node::Element(Tag { name: "anotertoplevelthinglolhaha".to_string(), attrs: vec![], id: None, classes: vec![], children: vec![], self_closing: false, span: 83..109 })]);
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(render("p a < b & c\n"), "<p>a &lt; b &amp; c</p>");
+        assert_eq!(
+            render("a(title=\"x\\\"y\") hi\n"),
+            "<a title=\"x&quot;y\">hi</a>"
+        );
+        assert_eq!(render("p!= <b>raw</b>\n"), "<p><b>raw</b></p>");
+        assert_eq!(
+            render("a(href!=\"/?a=1&b=2\") link\n"),
+            "<a href=\"/?a=1&b=2\">link</a>"
+        );
+        assert_eq!(
+            render("a(title='it\\'s', alt=\"a\\\\b\") hi\n"),
+            "<a title=\"it&#39;s\" alt=\"a\\b\">hi</a>"
+        );
+
+        let input = "a(title=\"C:\\new\") hi\n";
+        let (_, errors) = crate::parse_recovering(input, &Default::default());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code(), "P0012");
+        assert_eq!(&input[errors[0].span()], "\\n");
+        assert_eq!(errors[0].to_string(), "unknown escape `\\n`");
+    }
+
+    #[test]
+    fn last_line_without_line_break() {
+        assert_eq!(render("p!= <b>raw</b>"), "<p><b>raw</b></p>");
+        assert_eq!(render("ul\n\tli one"), "<ul><li>one</li></ul>");
+    }
+
+    #[test]
+    fn indent_after_top_level_line() {
+        // a line with no indentation resets the level, so the next indented line is one level in
+        assert_eq!(
+            render("ul\n\tli one\np\n\tspan two\n"),
+            "<ul><li>one</li></ul><p><span>two</span></p>"
+        );
+    }
+
+    #[test]
+    fn render_modes() {
+        let input = "p\n\tinput(disabled)\n\tbr\n";
+        assert_eq!(render(input), "<p><input disabled><br></p>");
+        assert_eq!(
+            render_with(
+                input,
+                &RenderOptions {
+                    mode: Some(Mode::Xml),
+                    ..Default::default()
+                }
+            ),
+            "<p><input disabled=\"disabled\"/><br/></p>"
+        );
+    }
+
+    #[test]
+    fn attribute_order() {
+        assert_eq!(
+            render("a#home.link(href=\"/\", title=\"Home\", target=\"_self\", rel=\"me\") home\n"),
+            "<a id=\"home\" class=\"link\" href=\"/\" title=\"Home\" target=\"_self\" rel=\"me\">home</a>"
+        );
+    }
+
+    #[test]
+    fn class_and_id_merging() {
+        assert_eq!(
+            render("div.a.b(class=\"b c\")\n"),
+            "<div class=\"a b c\"></div>"
+        );
+        assert_eq!(
+            render("div(title=\"x\", id=\"main\")\n"),
+            "<div id=\"main\" title=\"x\"></div>"
+        );
+        assert_eq!(render("div#main(id=\"main\")\n"), "<div id=\"main\"></div>");
+
+        assert!(matches!(
+            crate::parse("div#x(id=\"y\")\n"),
+            Err(crate::errors::Error::Parser(
+                crate::errors::ParserError::ConflictingId { span, first, .. }
+            )) if span == (6..12) && first == (3..5)
+        ));
+        assert!(matches!(
+            crate::parse("a(href=\"/\", href=\"/x\")\n"),
+            Err(crate::errors::Error::Parser(
+                crate::errors::ParserError::DuplicateAttribute { span, first, .. }
+            )) if span == (12..21) && first == (2..10)
+        ));
+
+        // without a value or unescaped, they couldn't be merged
+        for (input, span) in [
+            ("div.a(class)\n", 6..11),
+            ("div#x(id)\n", 6..8),
+            ("div(class!=\"<b>\")\n", 4..16),
+            ("div#x(id!=\"x\")\n", 6..13),
+        ] {
+            let error = crate::parse(input).unwrap_err();
+            assert_eq!(error.code(), "P0017", "{input}");
+            assert_eq!(error.span(), span, "{input}");
+        }
+        assert_eq!(
+            crate::parse("div(class)\n").unwrap_err().to_string(),
+            "`class` attribute needs an escaped value, like `class=\"...\"`"
+        );
+    }
+
+    #[test]
+    fn pretty() {
+        let options = RenderOptions {
+            indent: Some("  ".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            render_with(
+                "ul#menu\n\tli one\n\tli\n\t\ta(href=\"/\") two\n\tli\n\t\tpre\n\t\t\tdiv three\n",
+                &options
+            ),
+            "<ul id=\"menu\">\n  <li>one</li>\n  <li><a href=\"/\">two</a></li>\n  <li>\n    <pre><div>three</div></pre>\n  </li>\n</ul>"
+        );
+    }
+
+    #[test]
+    fn render_io() {
+        let document = crate::parse("ul\n\tli a & b\n\tli(class=\"last\") c\n").unwrap();
+        let mut bytes = vec![];
+        crate::render::render_io(&mut bytes, &document, &RenderOptions::default()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "<ul><li>a &amp; b</li><li class=\"last\">c</li></ul>"
+        );
+    }
+
+    #[test]
+    fn doctype() {
+        assert_eq!(
+            render("doctype html\nhtml\n\tbody\n\t\tbr\n"),
+            "<!DOCTYPE html><html><body><br></body></html>"
+        );
+        assert_eq!(
+            render("doctype xml\nfeed\n\tlink(rel=\"self\", hidden)\n"),
+            "<?xml version=\"1.0\" encoding=\"utf-8\" ?><feed><link rel=\"self\" hidden=\"hidden\"/></feed>"
+        );
+        assert_eq!(
+            render_with(
+                "doctype strict\nbr\n",
+                &RenderOptions {
+                    mode: Some(Mode::Html),
+                    ..Default::default()
+                }
+            ),
+            "<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\" \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd\"><br>"
+        );
+        assert_eq!(render("doctype\n"), "<!DOCTYPE html>");
+        assert_eq!(render("doctype svg PUBLIC\n"), "<!DOCTYPE svg PUBLIC>");
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            render("// hello\np hi\n//- hidden\n"),
+            "<!-- hello--><p>hi</p>"
+        );
+        assert_eq!(
+            render("div\n\t//\n\t\tp one\n\t\t\tp two\n\tp three\n"),
+            "<div><!--\np one\n\tp two--><p>three</p></div>"
+        );
+        assert_eq!(
+            render("div\n\t//- secret\n\t\tp one\np two\n"),
+            "<div></div><p>two</p>"
+        );
+        assert_eq!(
+            render_with(
+                "ul\n\t//- hidden\n\tli one\n",
+                &RenderOptions {
+                    indent: Some("  ".to_string()),
+                    ..Default::default()
+                }
+            ),
+            "<ul>\n  <li>one</li>\n</ul>"
+        );
+
+        let input = "// a --> <script>alert(1)</script>\np hi\n";
+        let (document, errors) = crate::parse_recovering(input, &Default::default());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code(), "P0013");
+        assert_eq!(errors[0].span(), 5..7);
+        let mut html = String::new();
+        crate::render::render(&mut html, &document, &RenderOptions::default()).unwrap();
+        assert_eq!(html, "<p>hi</p>");
+
+        let (_, errors) = crate::parse_recovering("//\n\tone\n\ttwo -\n", &Default::default());
+        assert_eq!(errors[0].code(), "P0013");
+        assert_eq!(render("//- a --> b\n"), "");
+    }
+
+    #[test]
+    fn piped_and_block_text() {
+        assert_eq!(
+            render("p\n\t| Hello\n\tb world\n\t| again &\n\t| more\n"),
+            "<p>Hello<b>world</b>again &amp;\nmore</p>"
+        );
+        assert_eq!(
+            render("p.\n\tFirst line\n\t\tindented <b>\n\tlast line\ndiv\n"),
+            "<p>First line\n\tindented &lt;b&gt;\nlast line</p><div></div>"
+        );
+        assert_eq!(
+            render("script.\n\tif (a < b && c) {}\n"),
+            "<script>if (a < b && c) {}</script>"
+        );
+        assert_eq!(
+            render("script a < b\nstyle\n\t| </style>\n"),
+            "<script>a &lt; b</script><style>&lt;/style&gt;</style>"
+        );
+        assert_eq!(render("script!= a < b\n"), "<script>a < b</script>");
+        assert_eq!(
+            render("script.\n  function f() {\n    // note\n    go()\n  }\n  $(x) < y\np\n"),
+            "<script>function f() {\n  // note\n  go()\n}\n$(x) < y</script><p></p>"
+        );
+        assert_eq!(
+            render("div.box.\n\ttext\n"),
+            "<div class=\"box\">text</div>"
+        );
+    }
+
+    #[test]
+    fn mixed_content() {
+        let text = |text: &str, span| {
+            Node::Text(Text {
+                text: text.to_string(),
+                raw: false,
+                span,
+            })
+        };
+
+        test_str(
+            "p Hello\n\tb world\n\t| again\n",
+            vec![Node::Element(Tag {
+                name: "p".to_string(),
+                attrs: vec![],
+                id: None,
+                classes: vec![],
+                children: vec![
+                    text("Hello", 2..7),
+                    Node::Element(Tag {
+                        name: "b".to_string(),
+                        attrs: vec![],
+                        id: None,
+                        classes: vec![],
+                        children: vec![text("world", 11..16)],
+                        self_closing: false,
+                        span: 9..16,
+                    }),
+                    text("again", 18..25),
+                ],
+                self_closing: false,
+                span: 0..25,
+            })],
+        );
+    }
+
+    #[test]
+    fn spans() {
+        let input =
+            "ul\n\tli.item(data-x=\"1\", hidden) one\n\t//- note\n\tp.\n\t\tsome\n\t\ttext\n";
+        let document = crate::parse(input).unwrap();
+        let slice = |span: &std::ops::Range<usize>| &input[span.clone()];
+
+        let Node::Element(ul) = &document.nodes[0] else {
+            panic!("expected an element, got {:?}", document.nodes[0]);
+        };
+        assert_eq!(slice(&ul.span), &input[..input.len() - 1]);
+
+        let [Node::Element(li), Node::Comment(comment), Node::Element(p)] = ul.children.as_slice()
+        else {
+            panic!("unexpected children {:?}", ul.children);
+        };
+        assert_eq!(slice(&li.span), "li.item(data-x=\"1\", hidden) one");
+        assert_eq!(slice(&li.classes[0].span), ".item");
+        assert_eq!(slice(&li.attrs[0].span), "data-x=\"1\"");
+        assert_eq!(slice(&li.attrs[1].span), "hidden");
+        assert_eq!(slice(&li.children[0].span()), "one");
+        assert_eq!(slice(&comment.span), "//- note");
+        assert_eq!(slice(&p.span), "p.\n\t\tsome\n\t\ttext");
+        assert_eq!(slice(&p.children[0].span()), "some\n\t\ttext");
+    }
+
+    #[test]
+    fn diagnostics() {
+        let input = "ul\n\tli\n\t\ta(href=\"/\", href=\"/x\") home\n";
+        let error = crate::parse(input).unwrap_err();
+
+        assert_eq!(
+            error.diagnostic("menu.purgs", input).to_string(),
+            "error[P0005]: duplicate attribute `href`
+ --> menu.purgs:3:15
+  |
+3 |         a(href=\"/\", href=\"/x\") home
+  |                     ^^^^^^^^^
+note: first set here
+ --> menu.purgs:3:5
+  |
+3 |         a(href=\"/\", href=\"/x\") home
+  |           ^^^^^^^^"
+        );
+
+        use crate::diagnostic::Location;
+        assert_eq!(Location::of("p\n\tä b", 5), Location { line: 2, column: 3 });
+        // the end of a file with a trailing line break is the end of its last line
+        assert_eq!(Location::of("p\nb\n", 4), Location { line: 2, column: 2 });
+
+        // the message shows the text that was found, not just that it was text
+        let error = crate::parse("a(href=\"/\")home2 x\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "expected a line break, found text `home2`"
+        );
+    }
+
+    #[test]
+    fn error_recovery() {
+        let input = "ul\n\tli(a=\"1\", a=\"2\") one\n\t\tspan nested\n\tli two\n\tli#x(id=\"y\") three\np after\n";
+        let (document, errors) = crate::parse_recovering(input, &Default::default());
+
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                crate::errors::Error::Parser(crate::errors::ParserError::DuplicateAttribute { .. }),
+                crate::errors::Error::Parser(crate::errors::ParserError::ConflictingId { .. }),
+            ]
+        ));
+
+        let Node::Element(ul) = &document.nodes[0] else {
+            panic!("expected an element, got {:?}", document.nodes[0]);
+        };
+        let [Node::Error(first), Node::Element(li), Node::Error(last)] = ul.children.as_slice()
+        else {
+            panic!("unexpected children {:?}", ul.children);
+        };
+        assert_eq!(
+            &input[first.clone()],
+            "li(a=\"1\", a=\"2\") one\n\t\tspan nested"
+        );
+        assert_eq!(li.name, "li");
+        assert_eq!(&input[last.clone()], "li#x(id=\"y\") three");
+
+        let mut html = String::new();
+        crate::render::render(&mut html, &document, &RenderOptions::default()).unwrap();
+        assert_eq!(html, "<ul><li>two</li></ul><p>after</p>");
+    }
+
+    #[test]
+    fn error_codes() {
+        let (_, errors) = crate::parse_recovering("a(b, b)\np#x(id=\"y\")\n", &Default::default());
+        let codes: Vec<_> = errors.iter().map(|error| error.code()).collect();
+        assert_eq!(codes, ["P0005", "P0006"]);
+
+        // stuff the parser can't make sense of is only reported once, by the lexer
+        let input = "$div text\np costs $5\n";
+        let (_, errors) = crate::parse_recovering(input, &Default::default());
+        let codes: Vec<_> = errors.iter().map(|error| error.code()).collect();
+        assert_eq!(codes, ["P0007"]);
+        assert_eq!(errors[0].span(), 0..1);
+
+        // an indented first line is skipped, the dedent after it doesn't end the document
+        let (document, errors) = crate::parse_recovering("  p\nq\n", &Default::default());
+        let codes: Vec<_> = errors.iter().map(|error| error.code()).collect();
+        assert_eq!(codes, ["P0001"]);
+        assert_eq!(errors[0].span(), 0..2);
+        let [Node::Error(first), Node::Element(q)] = document.nodes.as_slice() else {
+            panic!("unexpected nodes {:?}", document.nodes);
+        };
+        assert_eq!(*first, 0..3);
+        assert_eq!(q.name, "q");
+
+        let codes = [
+            "P0001", "P0002", "P0003", "P0005", "P0006", "P0007", "P0008", "P0009", "P0010",
+            "P0011", "P0012", "P0013", "P0014", "P0015", "P0016", "P0017",
+        ];
+        for code in codes {
+            assert!(
+                crate::explain::explain(code).is_some(),
+                "{code} isn't explained"
+            );
+        }
+        assert!(crate::explain::explain("p0001").is_some());
+        assert!(crate::explain::explain("P0000").is_none());
+        assert!(crate::explain::explain("P0004").is_none());
+    }
+
+    #[test]
+    fn json_diagnostics() {
+        let input = "p\n\ta(b, b) \"quoted\"\n";
+        let error = crate::parse(input).unwrap_err();
+        let mut json = String::new();
+        error
+            .diagnostic("dir\\\"x\".purgs", input)
+            .write_json(&mut json)
+            .unwrap();
+
+        assert_eq!(
+            json,
+            r#"{"code":"P0005","severity":"error","message":"duplicate attribute `b`","file":"dir\\\"x\".purgs","span":{"start":8,"end":9,"line":2,"column":7,"end_line":2,"end_column":8},"notes":[{"message":"first set here","span":{"start":5,"end":6,"line":2,"column":4,"end_line":2,"end_column":5}}]}"#
+        );
+    }
+
+    #[test]
+    fn lexer_spans() {
+        use crate::lexer::{Lexer, Token};
+        use std::cell::RefCell;
+
+        let input = "a(b='c')\n\t$";
+        let errors = RefCell::default();
+        let tokens: Vec<_> = Lexer::new(input, None, &errors).collect();
+
+        assert_eq!(
+            tokens,
+            [
+                (Token::Text, 0..1),
+                (Token::OpenParen, 1..2),
+                (Token::Text, 2..3),
+                (Token::Equals, 3..4),
+                (Token::Quote('\''), 4..5),
+                (Token::Text, 5..6),
+                (Token::Quote('\''), 6..7),
+                (Token::CloseParen, 7..8),
+                (Token::Newline, 8..9),
+                (Token::Indent, 9..10),
+                (Token::Error, 10..11),
+            ]
+        );
+        let errors = errors.into_inner();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].span(), 10..11);
+
+        let errors = RefCell::default();
+        let tokens: Vec<_> = Lexer::new("p   x", None, &errors).collect();
+        assert_eq!(
+            tokens,
+            [
+                (Token::Text, 0..1),
+                (Token::WhiteSpace, 1..4),
+                (Token::Text, 4..5)
+            ]
+        );
+    }
+
+    #[test]
+    fn interpolation() {
+        assert_eq!(
+            render("p This is #[strong important] text\n"),
+            "<p>This is <strong>important</strong> text</p>"
+        );
+        assert_eq!(
+            render("p #[a.nav(href=\"/x\") a #[em nested] link]\n"),
+            "<p><a class=\"nav\" href=\"/x\">a <em>nested</em> link</a></p>"
+        );
+        assert_eq!(
+            render("p\n\t| see #[br] [1] and \\#[b] or # and ]\n"),
+            "<p>see <br> [1] and #[b] or # and ]</p>"
+        );
+        assert_eq!(render("p #[b [x]]\n"), "<p><b>[x]</b></p>");
+
+        let Node::Element(p) = &crate::parse("p a #[b] c\n").unwrap().nodes[0] else {
+            unreachable!()
+        };
+        assert_eq!(p.children[1].span(), 4..8);
+        assert!(matches!(
+            crate::parse("p a #[b c\n"),
+            Err(crate::errors::Error::Parser(
+                crate::errors::ParserError::UnclosedInterpolation { span }
+            )) if span == (4..6)
+        ));
+    }
+
+    #[test]
+    fn block_expansion() {
+        assert_eq!(
+            render("ul\n\tli: a(href=\"/\") Home\n\tli.x: a: span\n\t\tb deep\n"),
+            render("ul\n\tli\n\t\ta(href=\"/\") Home\n\tli.x\n\t\ta\n\t\t\tspan\n\t\t\t\tb deep\n")
+        );
+
+        let input = "li: a(href=\"/\") Home\n";
+        let Node::Element(li) = &crate::parse(input).unwrap().nodes[0] else {
+            unreachable!()
+        };
+        assert_eq!(li.span, 0..input.len() - 1);
+        assert!(
+            matches!(&li.children[..], [Node::Element(a)] if a.name == "a" && a.span == (4..20))
+        );
+
+        let (_, errors) = crate::parse_recovering("li:\nli: \nli: | Home\n", &Default::default());
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| (error.code(), error.span()))
+            .collect();
+        assert_eq!(
+            errors,
+            [("P0014", 2..3), ("P0014", 6..7), ("P0014", 11..12)]
+        );
+    }
+
+    #[test]
+    fn self_closing() {
+        assert_eq!(
+            render("svg\n\tpath(d=\"M 0 0\")/\n\tfoo/\n\tbr/\nbr\n"),
+            "<svg><path d=\"M 0 0\"/><foo/><br/></svg><br>"
+        );
+
+        let (_, errors) =
+            crate::parse_recovering("g/ text\ng/\n\tcircle/\np ok\n", &Default::default());
+        let spans: Vec<_> = errors
+            .iter()
+            .map(|error| match error {
+                crate::errors::Error::Parser(
+                    crate::errors::ParserError::SelfClosingWithContent { span, slash, .. },
+                ) => (span.clone(), slash.clone()),
+                error => panic!("unexpected error {error:?}"),
+            })
+            .collect();
+        assert_eq!(spans, [(3..7, 1..2), (12..19, 9..10)]);
+
+        // the same goes for tags in text
+        assert_eq!(
+            render("p a #[path/] and #[img(src=\"x\")/] b\n"),
+            "<p>a <path/> and <img src=\"x\"/> b</p>"
+        );
+        assert!(matches!(
+            crate::parse("p a #[br/ x]\n"),
+            Err(crate::errors::Error::Parser(
+                crate::errors::ParserError::SelfClosingWithContent { span, slash, .. }
+            )) if span == (10..11) && slash == (8..9)
+        ));
+
+        // void elements can't have content either, whether they end in `/` or not
+        let (_, errors) = crate::parse_recovering(
+            "br text\nimg\n\tp x\ninput: b\np a #[br x] y\np ok\n",
+            &Default::default(),
+        );
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| (error.code(), error.span()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                ("P0009", 3..7),
+                ("P0009", 13..16),
+                ("P0009", 24..25),
+                ("P0009", 35..36)
+            ]
+        );
+    }
+
+    #[test]
+    fn multi_line_attributes() {
+        assert_eq!(
+            render("form\n\tinput(\n\t\ttype=\"text\"\n\t\tname = \"a (b\"\n\t\t\tdisabled,,  value=\"x\"\n\t)\n\tp next\np after\n"),
+            "<form><input type=\"text\" name=\"a (b\" disabled value=\"x\"><p>next</p></form><p>after</p>"
+        );
+        // a `(` in text doesn't start an attribute list
+        assert_eq!(
+            render("p see (below\n\tspan x\np.\n\tcall(a\nb\n"),
+            "<p>see (below<span>x</span></p><p>call(a</p><b></b>"
+        );
+        assert_eq!(
+            render("a(\n    href=\"/\"\n    title=\"Home\")\nb\n"),
+            "<a href=\"/\" title=\"Home\"></a><b></b>"
+        );
+
+        // an unclosed `(` ends at the next line that's back at the tag's indentation
+        let input = "ul\n\tli(a=\"1\"\n\t\tspan x\n\tli two\np(b=\"c\n";
+        let (document, errors) = crate::parse_recovering(input, &Default::default());
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| (error.code(), error.span()))
+            .collect();
+        assert_eq!(errors, [("P0015", 6..7), ("P0001", 36..37)]);
+        let mut html = String::new();
+        crate::render::render(&mut html, &document, &RenderOptions::default()).unwrap();
+        assert_eq!(html, "<ul><li>two</li></ul>");
+
+        // an escaped backslash doesn't escape the quote after it
+        assert_eq!(
+            render("a(title=\"a\\\\\", b=\"(\\\"\")\n"),
+            "<a title=\"a\\\" b=\"(&quot;\"></a>"
+        );
+    }
+
+    #[test]
+    fn framework_attributes() {
+        assert_eq!(
+            render("button#save_btn.btn_primary(@click=\"save\", :value=\"x\", x-on:submit.prevent, v-bind:class=\"c\", data_x=\"1\", aria-label_x=\"l\", #default) Save\n"),
+            "<button id=\"save_btn\" class=\"btn_primary\" @click=\"save\" :value=\"x\" x-on:submit.prevent v-bind:class=\"c\" data_x=\"1\" aria-label_x=\"l\" #default>Save</button>"
+        );
+        assert_eq!(
+            render("svg\n\tuse(xlink:href=\"#icon\")/\n"),
+            "<svg><use xlink:href=\"#icon\"/></svg>"
+        );
+    }
+
+    #[test]
+    fn unicode() {
+        assert_eq!(
+            render("section#größe.überschrift.标题(data-名前=\"値\") Ça va? 🙂 #[em ё]\n"),
+            "<section id=\"größe\" class=\"überschrift 标题\" data-名前=\"値\">Ça va? 🙂 <em>ё</em></section>"
+        );
+
+        let input = "p.日本\n\tspan(ä=\"1\", ä=\"2\")\n";
+        let error = crate::parse(input).unwrap_err();
+        assert_eq!(error.span(), 23..29);
+        assert_eq!(
+            error.diagnostic("x.purgs", input).location(),
+            crate::diagnostic::Location {
+                line: 2,
+                column: 14
+            }
+        );
+    }
+
+    #[test]
+    fn indent_width() {
+        let expected = "<ul><li><a>x</a></li><li>y</li></ul><p>z</p>";
+        assert_eq!(render("ul\n  li\n    a x\n  li y\np z\n"), expected);
+        assert_eq!(render("ul\n   li\n      a x\n   li y\np z\n"), expected);
+        assert_eq!(render("ul\n\tli\n\t\ta x\n\tli y\np z\n"), expected);
+
+        // text blocks can be indented however they like
+        assert_eq!(
+            render("div\n  pre.\n     a\n       b\n  p c\n"),
+            "<div><pre>a\n  b</pre><p>c</p></div>"
+        );
+
+        let input = "ul\n    li\n      a x\n";
+        let (_, errors) = crate::parse_recovering(input, &Default::default());
+        assert!(matches!(
+            errors.as_slice(),
+            [crate::errors::Error::Lexer(crate::errors::LexerError::Indentation {
+                width: 6,
+                unit: 4,
+                span,
+            })] if *span == (10..16)
+        ));
+
+        // a configured width wins over the first indented line
+        let options = crate::ParseOptions {
+            indent_width: Some(4),
+        };
+        let (_, errors) = crate::parse_recovering("ul\n  li x\n", &options);
+        assert!(matches!(
+            errors.as_slice(),
+            [crate::errors::Error::Lexer(
+                crate::errors::LexerError::Indentation {
+                    width: 2,
+                    unit: 4,
+                    ..
+                }
+            )]
+        ));
+        let (document, errors) = crate::parse_recovering("ul\n    li x\n", &options);
+        assert!(errors.is_empty());
+        let mut html = String::new();
+        crate::render::render(&mut html, &document, &RenderOptions::default()).unwrap();
+        assert_eq!(html, "<ul><li>x</li></ul>");
+
+        // the unit comes from the first indented line, so it's the misaligned line that's wrong
+        let input = "ul\n    li one\n  li two\n";
+        let (_, errors) = crate::parse_recovering(input, &Default::default());
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| (error.code(), error.span(), error.to_string()))
+            .collect();
+        assert_eq!(
+            errors,
+            [(
+                "P0010",
+                14..16,
+                "indentation of 2 spaces isn't a multiple of 4".to_string()
+            )]
+        );
+
+        let input = "ul\n  li\n      a x\n";
+        let (_, errors) = crate::parse_recovering(input, &Default::default());
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| (error.code(), error.span(), error.to_string()))
+            .collect();
+        assert_eq!(
+            errors,
+            [(
+                "P0016",
+                8..14,
+                "this line is indented 2 levels deeper than the line before it".to_string()
+            )]
+        );
+
+        // a run of spaces is one token, and only its first space separates a tag from its text
+        assert_eq!(
+            render("p   a  b #[b  c]\np!=  <i>\n|  d\n"),
+            "<p>  a  b <b> c</b></p><p> <i></p> d"
+        );
+    }
+
+    #[test]
+    fn mixed_indentation() {
+        let input = "ul\n    li one\n\tli two\n";
+        let (_, errors) = crate::parse_recovering(input, &Default::default());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code(), "P0011");
+        assert_eq!(errors[0].span(), 14..15);
+        assert_eq!(errors[0].related(), Some((3..7, "first indented here")));
+        assert_eq!(
+            errors[0].to_string(),
+            "this line is indented with tabs, but the file is indented with spaces"
+        );
+
+        // only the first line that's off is reported
+        let (_, errors) =
+            crate::parse_recovering("ul\n\tli\n  \ta x\n    li\n", &Default::default());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "this line is indented with tabs and spaces, but the file is indented with tabs"
+        );
+
+        assert_eq!(
+            crate::normalize_indentation(input, None),
+            "ul\n    li one\n    li two\n"
+        );
+        assert_eq!(
+            crate::normalize_indentation("ul\n\tli\n    a x\n", Some(2)),
+            "ul\n\tli\n\t\ta x\n"
+        );
+        assert_eq!(
+            render(&crate::normalize_indentation(input, None)),
+            "<ul><li>one</li><li>two</li></ul>"
+        );
+
+        // text blocks and attribute lists keep their own indentation past their tag's
+        let input = "ul\n  li one\n\tli(\n\t\t  title=\"x\") two\n\tpre.\n\t\t a\n\t\t   b\n";
+        let normalized = crate::normalize_indentation(input, None);
+        assert_eq!(
+            normalized,
+            "ul\n  li one\n  li(\n  \t  title=\"x\") two\n  pre.\n  \t a\n  \t   b\n"
+        );
+        assert_eq!(
+            render(&normalized),
+            "<ul><li>one</li><li title=\"x\">two</li><pre>a\n  b</pre></ul>"
+        );
+        // only lines that are all spaces decide how many make up a level
+        assert_eq!(
+            crate::normalize_indentation("ul\n\t  li\n    a x\n", None),
+            "ul\n\t  li\n\ta x\n"
+        );
+
+        // the file from the original report, and the same file indented consistently
+        let (_, errors) = crate::parse_recovering(
+            include_str!("tests/multi-dedent.purgs"),
+            &Default::default(),
+        );
+        assert_eq!(errors[0].code(), "P0011");
+        assert_eq!(
+            render(include_str!("tests/multi-dedent-clean.purgs")),
+            "<html><head></head><meta width=\"device-width=true\"><body></body><div id=\"content\" class=\"hello\">Hello World</div></html><anotertoplevelthinglolhaha></anotertoplevelthinglolhaha>"
+        );
+    }
+
+    #[test]
+    fn line_endings_and_blank_lines() {
+        let input = "div\n  p a\n  pre.\n    x\n\n      y\n  p b\n";
+        let expected = "<div><p>a</p><pre>x\n\n  y</pre><p>b</p></div>";
+        assert_eq!(render(input), expected);
+
+        for input in [
+            input.replace('\n', "\r\n"),
+            format!("\u{feff}{input}"),
+            input
+                .replace("  pre.", "\t \n\n  pre.")
+                .replace("  p b", "   \n  p b"),
+            format!("\n  \n{input}\n\n"),
+        ] {
+            let (_, errors) = crate::parse_recovering(&input, &Default::default());
+            assert!(errors.is_empty(), "{input:?}: {errors:?}");
+            assert_eq!(render(&input), expected, "{input:?}");
+        }
+
+        // trailing whitespace is only part of the text in text blocks
+        let expected = "<div><p>a</p><pre>x  \n  \n  y  </pre><p>b</p></div>";
+        for input in [
+            input.replace('\n', "  \n"),
+            format!("\u{feff}{}", input.replace('\n', "  \r\n")),
+        ] {
+            let (_, errors) = crate::parse_recovering(&input, &Default::default());
+            assert!(errors.is_empty(), "{input:?}: {errors:?}");
+            assert_eq!(render(&input), expected, "{input:?}");
+        }
+        assert_eq!(
+            render("textarea.\n\ta \t\n\t\tb  \r\np c  \n"),
+            "<textarea>a \t\n\tb  </textarea><p>c</p>"
+        );
+
+        // neither the byte order mark nor the `\r` count as columns
+        let input = "\u{feff}a$\r\np(a=\"x\r\n";
+        let (_, errors) = crate::parse_recovering(input, &Default::default());
+        let locations = errors
+            .iter()
+            .map(|error| {
+                error
+                    .diagnostic("index.purgs", input)
+                    .location()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(locations, ["1:2", "2:7"]);
     }
 }
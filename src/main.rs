@@ -1,10 +1,15 @@
+use std::io::{self, BufWriter, Write};
+use std::process::ExitCode;
 use std::{env, fs};
 
-use purgs::parse;
+use itertools::Itertools;
+use purgs::explain::explain;
+use purgs::render::{render_io, RenderOptions};
+use purgs::{normalize_indentation, parse_recovering, ParseOptions};
 use tracing::*;
 use tracing_subscriber::EnvFilter;
 
-fn main() {
+fn main() -> ExitCode {
     // debug logging
     tracing_subscriber::fmt()
         .without_time()
@@ -17,21 +22,118 @@ fn main() {
         )
         .init();
 
-    let tags = parse(
-        &fs::read_to_string(env::args().nth(1).expect("no file name argument specified"))
-            .expect("file not found"),
-    )
-    .unwrap_or_else(|e| {
-        error!("{e}");
-        panic!()
-    });
-
-    trace!("{:#?}", tags);
-    println!(
-        "{}",
-        tags.iter()
-            .map(|tag| tag.htmlify())
-            .collect::<Vec<_>>()
-            .join(""),
-    );
+    let mut args = env::args().skip(1).peekable();
+
+    if args.peek().is_some_and(|arg| arg == "explain") {
+        let Some(code) = args.nth(1) else {
+            eprintln!("error: explain takes an error code like P0001");
+            return ExitCode::FAILURE;
+        };
+        return match explain(&code) {
+            Some(explanation) => {
+                print!("{explanation}");
+                ExitCode::SUCCESS
+            }
+            None => {
+                eprintln!("error: {code} is not an error code");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let mut file_name = None;
+    let mut options = RenderOptions::default();
+    let mut parse_options = ParseOptions::default();
+    let mut json_errors = false;
+    let mut normalize = false;
+
+    for arg in args {
+        match arg.as_str() {
+            "--pretty" => options.indent = Some("  ".to_string()),
+            _ if arg.starts_with("--indent=") => {
+                let Ok(width) = arg["--indent=".len()..].parse() else {
+                    eprintln!("error: --indent takes the number of spaces to indent with");
+                    return ExitCode::FAILURE;
+                };
+                options.indent = Some(" ".repeat(width));
+            }
+            _ if arg.starts_with("--indent-width=") => {
+                let Ok(width @ 1..) = arg["--indent-width=".len()..].parse() else {
+                    eprintln!("error: --indent-width takes the number of spaces per level of indentation in the input");
+                    return ExitCode::FAILURE;
+                };
+                parse_options.indent_width = Some(width);
+            }
+            "--normalize" => normalize = true,
+            "--error-format=human" => json_errors = false,
+            "--error-format=json" => json_errors = true,
+            _ if arg.starts_with("--error-format=") => {
+                eprintln!("error: --error-format is either human or json");
+                return ExitCode::FAILURE;
+            }
+            _ => file_name = Some(arg),
+        }
+    }
+
+    let Some(file_name) = file_name else {
+        eprintln!("error: no file name argument specified");
+        return ExitCode::FAILURE;
+    };
+    let source = match fs::read_to_string(&file_name) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("error: couldn't read {file_name}: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // print the source with consistent indentation instead of compiling it
+    if normalize {
+        print!(
+            "{}",
+            normalize_indentation(&source, parse_options.indent_width)
+        );
+        return ExitCode::SUCCESS;
+    }
+
+    let (document, errors) = parse_recovering(&source, &parse_options);
+    if !errors.is_empty() {
+        if json_errors {
+            // one diagnostic per line
+            for error in &errors {
+                let mut json = String::new();
+                error
+                    .diagnostic(&file_name, &source)
+                    .write_json(&mut json)
+                    .expect("writing to a String can't fail");
+                eprintln!("{json}");
+            }
+            return ExitCode::FAILURE;
+        }
+
+        for error in &errors {
+            eprintln!("{}\n", error.diagnostic(&file_name, &source));
+        }
+        let plural = if errors.len() == 1 { "" } else { "s" };
+        eprintln!(
+            "error: could not compile {file_name} due to {} error{plural}",
+            errors.len()
+        );
+
+        let codes = errors.iter().map(|error| error.code()).unique().join(", ");
+        eprintln!("For more information about an error, try `purgs explain <code>` ({codes}).");
+        return ExitCode::FAILURE;
+    }
+
+    trace!("{:#?}", document);
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    if let Err(error) = render_io(&mut stdout, &document, &options)
+        .and_then(|()| writeln!(stdout))
+        .and_then(|()| stdout.flush())
+    {
+        eprintln!("error: couldn't write the output: {error}");
+        return ExitCode::FAILURE;
+    }
+
+    ExitCode::SUCCESS
 }
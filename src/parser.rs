@@ -1,71 +1,210 @@
 use crate::lexer::{Lexer, Token};
+use crate::render::{RAW_TEXT_ELEMENTS, VOID_ELEMENTS};
 use crate::{ast::*, errors::*};
 use aott::input::SpannedInput;
 use aott::{prelude::*, select};
-use std::collections::HashMap;
+use std::cell::RefCell;
 use std::ops::Range;
 use std::vec;
 use tracing::*;
 
 #[parser(extras=Extra)]
 #[instrument(level = "trace", skip(input), ret, err, parent = None)]
-fn parse_string(input: Tokens) -> String {
-    let quote_char = select!(Token::Quote(slice) => slice).parse_with(input)?;
+fn parse_string<'a>(input: Tokens<'a>) -> String {
+    let quote_char = select!(Token::Quote(quote) => quote).parse_with(input)?;
 
     let start = input.offset();
+    let mut escaped = false;
 
     loop {
-        if let Token::Quote(slice) = input.peek()? {
-            if slice == quote_char {
-                let result = input.context()[input.span_since(start)].to_owned();
+        let before = input.offset();
+        match input.peek()? {
+            Token::Quote(quote) if quote == quote_char => {
+                let span = input.span_since(start);
+                let result = &input.context().source[span.clone()];
+                let result = if escaped {
+                    unescape(result, span.start)?
+                } else {
+                    result.to_owned()
+                };
                 input.skip()?;
                 return Ok(result);
             }
-        } else {
-            input.skip()?;
+            Token::Backslash => {
+                // skip the backslash and whatever it escapes, so an escaped quote doesn't end the string
+                input.skip()?;
+                input.skip()?;
+                escaped = true;
+            }
+            // a string can span lines only in an attribute list that does
+            found @ (Token::Newline | Token::Indent | Token::Dedent) => {
+                input.skip()?;
+                return Err(ParserError::ExpectedToken {
+                    expected: vec![Token::Quote(quote_char)],
+                    found,
+                    span: input.span_since(before),
+                    slice: None,
+                });
+            }
+            _ => input.skip()?,
+        }
+    }
+}
+
+/// A name or a word, sliced out of the source
+#[parser(extras=Extra)]
+#[instrument(level = "trace", skip(input), ret, err, parent = None)]
+fn parse_text<'a>(input: Tokens<'a>) -> String {
+    let before = input.offset();
+    select!(Token::Text => ()).parse_with(input)?;
+
+    Ok(input.context().source[input.span_since(before)].to_owned())
+}
+
+/// An attribute name, which can also be written like the attributes of frontend frameworks and
+/// XML namespaces: `data_x`, `xlink:href`, `@click`, `:value`, `x-on:submit.prevent`, `#slot`
+#[parser(extras=Extra)]
+#[instrument(level = "trace", skip(input), ret, err, parent = None)]
+fn parse_attribute_name<'a>(input: Tokens<'a>) -> String {
+    let before = input.offset();
+    select!(Token::Text | Token::Colon | Token::At | Token::Hash => ()).parse_with(input)?;
+
+    while let Token::Text | Token::Colon | Token::At | Token::Hash | Token::Dot = input.peek()? {
+        input.skip()?;
+    }
+
+    Ok(input.context().source[input.span_since(before)].to_owned())
+}
+
+/// Resolves the escapes in a string literal that starts at `offset` in the source. Only `\"`, `\'`
+/// and `\\` are escapes, a backslash in front of anything else is an error.
+fn unescape(string: &str, offset: usize) -> Result<String, ParserError> {
+    let mut result = String::with_capacity(string.len());
+    let mut chars = string.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some((_, escaped @ ('"' | '\'' | '\\'))) => result.push(escaped),
+            Some((j, escape)) => {
+                return Err(ParserError::UnknownEscape {
+                    escape,
+                    span: offset + i..offset + j + escape.len_utf8(),
+                })
+            }
+            // the backslash would have escaped the closing quote
+            None => unreachable!("string literals can't end in a backslash"),
         }
     }
+
+    Ok(result)
 }
 
 #[parser(extras=Extra)]
 #[instrument(level = "trace", skip(input), ret, err, parent = None)]
-fn parse_attributes(input: Tokens) -> HashMap<String, Option<String>> {
-    let mut attrs = HashMap::new();
+fn parse_attributes<'a>(input: Tokens<'a>) -> Vec<Attribute> {
+    let before = input.offset();
+    select!(Token::OpenParen => ()).parse_with(input)?;
+    let open = input.span_since(before);
+
+    let mut attrs: Vec<Attribute> = vec![];
 
     loop {
-        if let Token::CloseParen = input.peek()? {
+        // attributes can be separated by any mix of commas, spaces and line breaks
+        while let Ok(Token::Comma | Token::WhiteSpace) = input.peek() {
             input.skip()?;
-            return Ok(attrs);
         }
-        let attr_name = select!(Token::Text(text) => text).parse_with(input)?;
-        if let Token::Equals = input.peek()? {
-            input.skip()?;
 
-            let attr_value = parse_string(input)?;
-            attrs.insert(attr_name, Some(attr_value));
-        } else {
-            attrs.insert(attr_name, None);
+        match input.peek() {
+            Ok(Token::CloseParen) => {
+                input.skip()?;
+                return Ok(attrs);
+            }
+            // the lexer ends the list at a line that isn't indented past the tag
+            Ok(Token::Newline | Token::Indent | Token::Dedent) | Err(_) => {
+                return Err(ParserError::UnclosedParen { span: open });
+            }
+            Ok(_) => {}
         }
+        let before = input.offset();
+        let attr_name = parse_attribute_name(input)?;
 
-        if let Token::Comma = input.peek()? {
+        // `name = "value"` is fine too, but a space can also end a boolean attribute
+        let offset = input.save();
+        while let Ok(Token::WhiteSpace) = input.peek() {
             input.skip()?;
         }
+        if !matches!(input.peek(), Ok(Token::Equals | Token::Bang)) {
+            input.rewind(offset);
+        }
 
-        if let Token::WhiteSpace = input.peek()? {
+        let raw = if let Ok(Token::Bang) = input.peek() {
             input.skip()?;
+            true
+        } else {
+            false
+        };
+
+        let value = if raw || matches!(input.peek(), Ok(Token::Equals)) {
+            select!(Token::Equals => ()).parse_with(input)?;
+            while let Ok(Token::WhiteSpace) = input.peek() {
+                input.skip()?;
+            }
+
+            let value = parse_string(input)?;
+            Some(if raw {
+                AttrValue::raw(value)
+            } else {
+                AttrValue::new(value)
+            })
+        } else {
+            None
+        };
+        let span = input.span_since(before);
+
+        if let Some(first) = attrs.iter().find(|attr| attr.name == attr_name) {
+            return Err(ParserError::DuplicateAttribute {
+                name: attr_name,
+                span,
+                first: first.span.clone(),
+            });
         }
+
+        attrs.push(Attribute {
+            name: attr_name,
+            value,
+            span,
+        });
     }
 }
 
 #[parser(extras=Extra)]
 #[instrument(level = "trace", skip(input), ret, err, parent = None)]
-fn parse_classes(input: Tokens) -> Vec<String> {
-    let mut classes = vec![];
+fn parse_classes<'a>(input: Tokens<'a>) -> Vec<Spanned<String>> {
+    let mut classes: Vec<Spanned<String>> = vec![];
 
     while let Token::Dot = input.peek()? {
+        let offset = input.save();
+        let before = input.offset();
         input.skip()?;
-        let class = select!(Token::Text(text) => text).parse_with(input)?;
-        classes.push(class);
+
+        // a dot that isn't followed by a class name starts a block of text (`p.`)
+        let Ok(Token::Text) = input.peek() else {
+            input.rewind(offset);
+            break;
+        };
+        let class = parse_text(input)?;
+
+        if !classes.iter().any(|existing| existing.value == class) {
+            classes.push(Spanned {
+                value: class,
+                span: input.span_since(before),
+            });
+        }
     }
 
     Ok(classes)
@@ -73,42 +212,240 @@ fn parse_classes(input: Tokens) -> Vec<String> {
 
 #[parser(extras=Extra)]
 #[instrument(level = "trace", skip(input), ret, err, parent = None)]
-fn parse_content(input: Tokens) -> String {
+fn parse_content<'a>(input: Tokens<'a>) -> Spanned<String> {
     let before = input.offset();
 
     loop {
-        if let Token::Newline = input.peek()? {
-            return Ok(input.context()[input.span_since(before)].to_owned());
-        } else {
-            input.skip()?;
+        match input.peek() {
+            // content on the last line ends at the end of the file
+            Ok(Token::Newline) | Err(_) => {
+                let span = input.span_since(before);
+                return Ok(Spanned {
+                    value: input.context().source[span.clone()].to_owned(),
+                    span,
+                });
+            }
+            Ok(_) => input.skip()?,
         }
     }
 }
 
 struct Extra;
-impl<I: InputType<Token = Token, Span = Range<usize>>> ParserExtras<I> for Extra {
-    type Context = String;
+impl<'a> ParserExtras<Tokens<'a>> for Extra {
+    type Context = Context<'a>;
     type Error = ParserError;
 }
 
-type Tokens = SpannedInput<Token, Range<usize>, Stream<Lexer>>;
+struct Context<'a> {
+    source: &'a str,
+    /// Errors that the parser recovered from, in the order they were found. They're kept outside
+    /// of the context, which is gone if the parser fails anyway.
+    errors: &'a RefCell<Vec<ParserError>>,
+}
+
+type Tokens<'a> = SpannedInput<Token, Range<usize>, Stream<Lexer<'a>>>;
 
+/// Text up to the end of the line, with `#[tag text]` interpolations as elements between it
 #[parser(extras=Extra)]
 #[instrument(level = "trace", skip(input), ret, err, parent = None)]
-fn tag(input: Tokens) -> Tag {
-    let mut name = "div".to_string();
-    if let Token::Text(name_) = input.peek()? {
+fn parse_inline_content<'a>(input: Tokens<'a>) -> Vec<Node> {
+    let mut nodes = parse_inline(input)?;
+
+    // a `]` that doesn't close an interpolation is just text
+    while let Ok(Token::CloseBracket) = input.peek() {
+        let before = input.offset();
         input.skip()?;
-        name = name_;
+        let span = input.span_since(before);
+        push_text(&mut nodes, &input.context().source[span.clone()], span);
+
+        for node in parse_inline(input)? {
+            push_inline(&mut nodes, node);
+        }
+    }
+
+    Ok(nodes)
+}
+
+/// Inline text and interpolations, up to the end of the line or a `]` that isn't part of the
+/// text
+#[parser(extras=Extra)]
+#[instrument(level = "trace", skip(input), ret, err, parent = None)]
+fn parse_inline<'a>(input: Tokens<'a>) -> Vec<Node> {
+    let mut nodes = vec![];
+    // brackets in the text have to be balanced to not end an interpolation early
+    let mut depth = 0;
+
+    loop {
+        match input.peek() {
+            Ok(Token::Newline) | Err(_) => break,
+            Ok(Token::CloseBracket) if depth == 0 => break,
+            Ok(Token::Hash) => {
+                let offset = input.save();
+                input.skip()?;
+                let interpolation = matches!(input.peek(), Ok(Token::OpenBracket));
+                input.rewind(offset);
+
+                if interpolation {
+                    push_inline(&mut nodes, Node::Element(parse_interpolation(input)?));
+                    continue;
+                }
+            }
+            // `\#[` is a literal `#[`
+            Ok(Token::Backslash) => {
+                let offset = input.save();
+                input.skip()?;
+                let before = input.offset();
+                if let Ok(Token::Hash) = input.peek() {
+                    input.skip()?;
+                    if let Ok(Token::OpenBracket) = input.peek() {
+                        input.skip()?;
+                        push_text(&mut nodes, "#[", input.span_since(before));
+                        continue;
+                    }
+                }
+                input.rewind(offset);
+            }
+            Ok(Token::OpenBracket) => depth += 1,
+            Ok(Token::CloseBracket) => depth -= 1,
+            Ok(_) => {}
+        }
+
+        let before = input.offset();
+        input.skip()?;
+        let span = input.span_since(before);
+        push_text(&mut nodes, &input.context().source[span.clone()], span);
+    }
+
+    Ok(nodes)
+}
+
+/// `#[tag text]` inside of text
+#[parser(extras=Extra)]
+#[instrument(level = "trace", skip(input), ret, err, parent = None)]
+fn parse_interpolation<'a>(input: Tokens<'a>) -> Tag {
+    let before = input.offset();
+    select!(Token::Hash => ()).parse_with(input)?;
+    select!(Token::OpenBracket => ()).parse_with(input)?;
+    let opening = input.span_since(before);
+
+    let mut tag = tag_head(input)?;
+
+    // `#[tag/]` is closed right away like `tag/` on a line of its own
+    let mut slash = None;
+    if let Ok(Token::Slash) = input.peek() {
+        let before = input.offset();
+        input.skip()?;
+        slash = Some(input.span_since(before));
+        tag.self_closing = true;
+    }
+
+    if let Some(spaces) = separator(input)? {
+        let content = input.offset();
+        let source = input.context().source;
+        tag.children = with_spaces(source, spaces, parse_inline(input)?);
+
+        if let (Some(slash), false) = (slash, tag.children.is_empty()) {
+            return Err(ParserError::SelfClosingWithContent {
+                name: tag.name,
+                span: input.span_since(content),
+                slash,
+            });
+        }
+        check_void(&tag.name, &tag.children)?;
+    }
+
+    let Ok(Token::CloseBracket) = input.peek() else {
+        return Err(ParserError::UnclosedInterpolation { span: opening });
+    };
+    input.skip()?;
+
+    tag.span = input.span_since(before);
+    Ok(tag)
+}
+
+/// The space between a tag or `|` and its text. Only the first space separates them, the rest of
+/// a run of spaces is part of the text, so where they are is returned.
+#[parser(extras=Extra)]
+fn separator<'a>(input: Tokens<'a>) -> Option<Range<usize>> {
+    let Ok(Token::WhiteSpace) = input.peek() else {
+        return Ok(None);
+    };
+    let before = input.offset();
+    input.skip()?;
+    let span = input.span_since(before);
+    Ok(Some(span.start + 1..span.end))
+}
+
+/// Puts the spaces left over by [`separator`] in front of the text after them
+fn with_spaces(source: &str, spaces: Range<usize>, nodes: Vec<Node>) -> Vec<Node> {
+    let mut result = vec![];
+    if !spaces.is_empty() {
+        push_text(&mut result, &source[spaces.clone()], spaces);
+    }
+    for node in nodes {
+        push_inline(&mut result, node);
+    }
+    result
+}
+
+/// Makes sure void elements like `br` are empty, they can't even have text in them
+fn check_void(name: &str, children: &[Node]) -> Result<(), ParserError> {
+    match (children.first(), children.last()) {
+        (Some(first), Some(last)) if VOID_ELEMENTS.contains(&name) => {
+            Err(ParserError::VoidWithContent {
+                name: name.to_owned(),
+                span: first.span().start..last.span().end,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Adds `node` to the inline nodes of a line, merging text that ends up next to each other
+fn push_inline(nodes: &mut Vec<Node>, node: Node) {
+    match (nodes.last_mut(), node) {
+        (Some(Node::Text(previous)), Node::Text(text)) => {
+            previous.text.push_str(&text.text);
+            previous.span.end = text.span.end;
+        }
+        (_, node) => nodes.push(node),
+    }
+}
+
+fn push_text(nodes: &mut Vec<Node>, text: &str, span: Range<usize>) {
+    push_inline(
+        nodes,
+        Node::Text(Text {
+            text: text.to_owned(),
+            raw: false,
+            span,
+        }),
+    );
+}
+
+/// The name, `#id`, `.classes` and `(attributes)` of a tag, without anything after them
+#[parser(extras=Extra)]
+#[instrument(level = "trace", skip(input), ret, err, parent = None)]
+fn tag_head<'a>(input: Tokens<'a>) -> Tag {
+    let start = input.offset();
+
+    let mut name = "div".to_string();
+    if let Token::Text = input.peek()? {
+        name = parse_text(input)?;
     }
 
     trace!(?name, "starting tag");
 
-    let mut id = None;
+    let mut id: Option<Spanned<String>> = None;
 
     if let Ok(Token::Hash) = input.peek() {
+        let before = input.offset();
         input.skip()?;
-        id = Some(select!(Token::Text(text) => text).parse_with(input)?);
+        let id_ = parse_text(input)?;
+        id = Some(Spanned {
+            value: id_,
+            span: input.span_since(before),
+        });
     }
 
     let mut classes = vec![];
@@ -117,66 +454,443 @@ fn tag(input: Tokens) -> Tag {
         classes = parse_classes(input)?;
     }
 
-    let mut attrs = HashMap::new();
+    let mut attrs = vec![];
 
     if let Ok(Token::OpenParen) = input.peek() {
-        input.skip()?;
-        attrs = parse_attributes(input)?;
+        // `class` and `id` attributes are merged with the `.class` and `#id` shorthands, which
+        // can't be left out or unescaped
+        for attr in parse_attributes(input)? {
+            match attr {
+                Attribute { name, value, span }
+                    if (name == "class" || name == "id")
+                        && !matches!(value, Some(AttrValue { raw: false, .. })) =>
+                {
+                    return Err(ParserError::UnmergeableAttribute { name, span })
+                }
+                Attribute {
+                    name,
+                    value: Some(value),
+                    span,
+                } if name == "class" => {
+                    for class in value.value.split_whitespace() {
+                        if !classes.iter().any(|existing| existing.value == class) {
+                            classes.push(Spanned {
+                                value: class.to_owned(),
+                                span: span.clone(),
+                            });
+                        }
+                    }
+                }
+                Attribute {
+                    name,
+                    value: Some(value),
+                    span,
+                } if name == "id" => match id {
+                    Some(first) if first.value != value.value => {
+                        return Err(ParserError::ConflictingId {
+                            id: value.value,
+                            span,
+                            first_id: first.value,
+                            first: first.span,
+                        })
+                    }
+                    Some(_) => {}
+                    None => {
+                        id = Some(Spanned {
+                            value: value.value,
+                            span,
+                        })
+                    }
+                },
+                attr => attrs.push(attr),
+            }
+        }
     }
 
-    let mut content: Option<String> = None;
-    if let Ok(Token::WhiteSpace) = input.peek() {
+    Ok(Tag {
+        name,
+        attrs,
+        id,
+        classes,
+        children: vec![],
+        self_closing: false,
+        span: input.span_since(start),
+    })
+}
+
+#[parser(extras=Extra)]
+#[instrument(level = "trace", skip(input), ret, err, parent = None)]
+fn tag<'a>(input: Tokens<'a>) -> Tag {
+    let start = input.offset();
+    let Tag {
+        name,
+        attrs,
+        id,
+        classes,
+        span: head,
+        ..
+    } = tag_head(input)?;
+
+    // `tag: child` nests a single child on the same line, which gets the rest of the line and
+    // the indented block below
+    if let Ok(Token::Colon) = input.peek() {
+        let before = input.offset();
         input.skip()?;
-        content = Some(parse_content(input)?);
+        let colon = input.span_since(before);
+        if let Ok(Token::WhiteSpace) = input.peek() {
+            input.skip()?;
+        }
+
+        // the child can't be left out, that would be an empty `div`
+        let Ok(Token::Text | Token::Hash | Token::Dot) = input.peek() else {
+            return Err(ParserError::ExpectedTag { span: colon });
+        };
+        let child = tag(input)?;
+        let children = vec![Node::Element(child)];
+        check_void(&name, &children)?;
+        return Ok(Tag {
+            name,
+            attrs,
+            id,
+            classes,
+            span: head.start..children[0].span().end,
+            children,
+            self_closing: false,
+        });
     }
 
-    let final_tag = Tag {
-        children: {
-            if let Ok(Token::Newline) = input.peek() {
-                let offset = input.save();
-                input.skip()?;
+    // `tag/` is closed right away, so there can't be anything in it
+    if let Ok(Token::Slash) = input.peek() {
+        let before = input.offset();
+        input.skip()?;
+        let slash = input.span_since(before);
 
-                match input.peek() {
-                    Ok(Token::Indent) => {
-                        trace!(?name, "indenting");
+        if let Ok(Token::WhiteSpace) = input.peek() {
+            input.skip()?;
+        }
+        let content = match input.peek() {
+            Ok(Token::Newline) | Err(_) => {
+                let offset = input.save();
+                let children = match (input.skip(), input.peek()) {
+                    (Ok(()), Ok(Token::Indent)) => {
                         input.skip()?;
                         file(input)?
                     }
-                    Ok(Token::Dedent) => {
-                        trace!(?name, on = ?input.peek()?, "dedenting");
-                        vec![]
-                    }
+                    _ => vec![],
+                };
+
+                match (children.first(), children.last()) {
+                    (Some(first), Some(last)) => Some(first.span().start..last.span().end),
                     _ => {
                         input.rewind(offset);
-                        trace!(?name, "newline but no children");
-                        vec![]
+                        None
                     }
                 }
-            } else {
-                trace!(?name, "no newline and no children");
-                vec![]
             }
-        },
+            Ok(_) => Some(parse_content(input)?.span),
+        };
+
+        if let Some(span) = content {
+            return Err(ParserError::SelfClosingWithContent { name, span, slash });
+        }
+
+        return Ok(Tag {
+            name,
+            attrs,
+            id,
+            classes,
+            children: vec![],
+            self_closing: true,
+            span: head.start..slash.end,
+        });
+    }
+
+    // `tag.` makes the indented block below the tag its text
+    let mut block_text = false;
+    if let Ok(Token::Dot) = input.peek() {
+        input.skip()?;
+        block_text = true;
+    }
+
+    // `tag!= content` writes the content without escaping it
+    let mut raw = false;
+    if let Ok(Token::Bang) = input.peek() {
+        input.skip()?;
+        select!(Token::Equals => ()).parse_with(input)?;
+        raw = true;
+    }
+
+    let mut children = vec![];
+
+    // the text after the tag on the same line comes first
+    if let Some(spaces) = separator(input)? {
+        let source = input.context().source;
+        if raw {
+            let content = parse_content(input)?;
+            let span = spaces.start..content.span.end;
+            children.push(Node::Text(Text {
+                text: source[span.clone()].to_owned(),
+                raw,
+                span,
+            }));
+        } else {
+            children = with_spaces(source, spaces, parse_inline_content(input)?);
+        }
+    }
+
+    // everything on the tag's own line
+    let head = input.span_since(start);
+
+    if block_text {
+        // only the text in a `script.` block is taken as-is, not `script text` on one line
+        let raw = RAW_TEXT_ELEMENTS.contains(&name.as_str());
+        children.extend(parse_block_text(input)?.map(|text| {
+            Node::Text(Text {
+                text: text.value,
+                raw,
+                span: text.span,
+            })
+        }));
+    } else if let Ok(Token::Newline) = input.peek() {
+        let offset = input.save();
+        input.skip()?;
+
+        match input.peek() {
+            Ok(Token::Indent) => {
+                trace!(?name, "indenting");
+                input.skip()?;
+                children.extend(file(input)?);
+            }
+            Ok(Token::Dedent) => {
+                trace!(?name, on = ?input.peek()?, "dedenting");
+            }
+            _ => {
+                input.rewind(offset);
+                trace!(?name, "newline but no children");
+            }
+        }
+    } else {
+        trace!(?name, "no newline and no children");
+    }
+
+    check_void(&name, &children)?;
+    let end = children
+        .last()
+        .map_or(head.end, |child| child.span().end.max(head.end));
+
+    let final_tag = Tag {
         name,
         attrs,
-        classes,
-        content,
         id,
+        classes,
+        children,
+        self_closing: false,
+        span: head.start..end,
     };
 
     Ok(final_tag)
 }
 
+/// Parses the indented block after a line (if there is one) as plain text, for things like block
+/// comments whose contents aren't tags
 #[parser(extras=Extra)]
 #[instrument(level = "trace", skip(input), ret, err, parent = None)]
-fn file(input: Tokens) -> Vec<Tag> {
-    let mut top_level_tags: Vec<Tag> = vec![];
+fn parse_block_text<'a>(input: Tokens<'a>) -> Option<Spanned<String>> {
+    let Ok(Token::Newline) = input.peek() else {
+        return Ok(None);
+    };
+    let offset = input.save();
+    input.skip()?;
+
+    let Ok(Token::Indent) = input.peek() else {
+        input.rewind(offset);
+        return Ok(None);
+    };
+
+    // start at the indent so the first line's indentation can be stripped from every line
+    let before = input.offset();
+    input.skip()?;
+    let start = input.span_since(before).start;
+    let mut end = start;
+    let mut depth = 1;
+
+    loop {
+        match input.peek() {
+            Ok(Token::Indent) => depth += 1,
+            Ok(Token::Dedent) => {
+                depth -= 1;
+                if depth == 0 {
+                    input.skip()?;
+                    break;
+                }
+            }
+            // the block ends at the last line's text, not the newline after it
+            Ok(Token::Newline) => {}
+            Ok(_) => {
+                let before = input.offset();
+                input.skip()?;
+                end = input.span_since(before).end;
+                continue;
+            }
+            Err(_) => break,
+        }
+        input.skip()?;
+    }
+
+    let block = &input.context().source[start..end];
+    let indentation = &block[..block.len() - block.trim_start_matches([' ', '\t']).len()];
+
+    Ok(Some(Spanned {
+        value: block
+            .lines()
+            .map(|line| {
+                // the rest of the line is kept as it is, `lines` only drops the `\r` of a `\r\n`
+                line.strip_prefix(indentation).unwrap_or(line)
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        span: start + indentation.len()..end,
+    }))
+}
+
+#[parser(extras=Extra)]
+#[instrument(level = "trace", skip(input), ret, err, parent = None)]
+fn parse_comment<'a>(input: Tokens<'a>) -> Comment {
+    let start = input.save();
+    let before = input.offset();
+    select!(Token::Slash => ()).parse_with(input)?;
+    select!(Token::Slash => ()).parse_with(input)?;
+
+    let line = if let Ok(Token::Newline) | Err(_) = input.peek() {
+        String::new()
+    } else {
+        parse_content(input)?.value
+    };
+    let mut span = input.span_since(before);
+
+    // `//-` is lexed as part of the text after it, so it's split off here
+    let (mut text, buffered) = match line.strip_prefix('-') {
+        Some(text) => (text.to_owned(), false),
+        None => (line, true),
+    };
+
+    if let Some(block) = parse_block_text(input)? {
+        text.push('\n');
+        text.push_str(&block.value);
+        span.end = block.span.end;
+    }
+
+    // `-->` would end the HTML comment early and let the rest of it through as markup
+    if buffered
+        && (text.contains("--")
+            || text.starts_with('>')
+            || text.starts_with("->")
+            || text.ends_with('-'))
+    {
+        let source = &input.context().source[span.clone()];
+        let span = match source[2..].find("--") {
+            Some(dashes) => span.start + 2 + dashes..span.start + 4 + dashes,
+            None => span,
+        };
+        // the whole comment, block and all, is skipped as one broken node
+        input.rewind(start);
+        return Err(ParserError::UnsafeComment { span });
+    }
+
+    Ok(Comment {
+        text,
+        buffered,
+        span,
+    })
+}
+
+#[parser(extras=Extra)]
+#[instrument(level = "trace", skip(input), ret, err, parent = None)]
+fn parse_piped_text<'a>(input: Tokens<'a>) -> Vec<Node> {
+    let before = input.offset();
+    select!(Token::Pipe => ()).parse_with(input)?;
+    let pipe = input.span_since(before);
+
+    let mut nodes = vec![];
+    if let Some(spaces) = separator(input)? {
+        nodes = with_spaces(input.context().source, spaces, parse_inline_content(input)?);
+    }
+
+    // the line's text starts at the `|`, and an empty `|` is an empty line of text
+    match nodes.first_mut() {
+        Some(Node::Text(text)) => text.span.start = pipe.start,
+        _ => nodes.insert(
+            0,
+            Node::Text(Text {
+                text: String::new(),
+                raw: false,
+                span: pipe,
+            }),
+        ),
+    }
+
+    Ok(nodes)
+}
+
+/// Everything that starts on one line: a tag, a comment, or `|` text and what's interpolated
+/// into it
+#[parser(extras=Extra)]
+#[instrument(level = "trace", skip(input), ret, err, parent = None)]
+fn node<'a>(input: Tokens<'a>) -> Vec<Node> {
+    match input.peek() {
+        Ok(Token::Slash) => Ok(vec![Node::Comment(parse_comment(input)?)]),
+        Ok(Token::Pipe) => parse_piped_text(input),
+        _ => Ok(vec![Node::Element(tag(input)?)]),
+    }
+}
+
+#[parser(extras=Extra)]
+#[instrument(level = "trace", skip(input), ret, err, parent = None)]
+fn file<'a>(input: Tokens<'a>) -> Vec<Node> {
+    let mut top_level_nodes: Vec<Node> = vec![];
 
     while input.peek().is_ok() {
-        top_level_tags.push(tag(input)?);
+        let before = input.offset();
+        let parsed = match node(input) {
+            Ok(nodes) => end_of_line(input).map(|()| nodes),
+            Err(error) => Err(error),
+        };
+
+        let nodes = match parsed {
+            Ok(nodes) => nodes,
+            // a broken line doesn't stop the rest of the file from being parsed
+            Err(error) => {
+                input.context().errors.borrow_mut().push(error);
+                let line = input.span_since(before);
+                let end = skip_line(input)?.map_or(line.end, |end| end.max(line.end));
+                vec![Node::Error(line.start..end)]
+            }
+        };
+
+        let mut nodes = nodes.into_iter();
+        match (top_level_nodes.last_mut(), nodes.next()) {
+            // consecutive `|` lines are one multi-line text
+            (Some(Node::Text(previous)), Some(Node::Text(text))) => {
+                previous.text.push('\n');
+                previous.text.push_str(&text.text);
+                previous.span.end = text.span.end;
+            }
+            (_, node) => top_level_nodes.extend(node),
+        }
+        top_level_nodes.extend(nodes);
 
         match input.peek() {
-            Ok(Token::Newline) => input.skip()?,
+            Ok(Token::Newline) => {
+                input.skip()?;
+
+                // only tags eat the newline before a dedent themselves, so it's handled here
+                // for text and comments
+                if let Ok(Token::Dedent) = input.peek() {
+                    input.skip()?;
+                    trace!("dedent from file");
+                    break;
+                }
+            }
             Ok(Token::Dedent) => {
                 input.skip()?;
                 trace!("dedent from file");
@@ -190,14 +904,211 @@ fn file(input: Tokens) -> Vec<Tag> {
     }
 
     trace!("peek no longer ok");
-    Ok(top_level_tags)
+    Ok(top_level_nodes)
+}
+
+/// Makes sure nothing is left on the line after a node
+#[parser(extras=Extra)]
+#[instrument(level = "trace", skip(input), ret, err, parent = None)]
+fn end_of_line<'a>(input: Tokens<'a>) -> () {
+    match input.peek() {
+        Ok(Token::Newline | Token::Dedent) | Err(_) => Ok(()),
+        Ok(found) => {
+            let before = input.offset();
+            input.skip()?;
+            Err(ParserError::ExpectedToken {
+                expected: vec![Token::Newline],
+                found,
+                span: input.span_since(before),
+                slice: None,
+            })
+        }
+    }
+}
+
+/// Skips the rest of a line that failed to parse and the block indented under it, so parsing
+/// can pick up again at the next line on the same level. Returns where the last skipped text
+/// ends, if anything but line breaks and indentation was skipped.
+#[parser(extras=Extra)]
+#[instrument(level = "trace", skip(input), ret, err, parent = None)]
+fn skip_line<'a>(input: Tokens<'a>) -> Option<usize> {
+    let mut end = None;
+
+    while !matches!(input.peek(), Ok(Token::Newline | Token::Dedent) | Err(_)) {
+        end = skip_token(input)?.or(end);
+    }
+
+    let offset = input.save();
+    if let Ok(Token::Newline) = input.peek() {
+        input.skip()?;
+        if let Ok(Token::Indent) = input.peek() {
+            input.skip()?;
+
+            let mut depth = 1;
+            while depth > 0 {
+                match input.peek() {
+                    Ok(Token::Indent) => depth += 1,
+                    Ok(Token::Dedent) => depth -= 1,
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+                end = skip_token(input)?.or(end);
+            }
+            return Ok(end);
+        }
+    }
+    input.rewind(offset);
+
+    Ok(end)
+}
+
+/// Skips one token, returning where it ends unless it's a line break or indentation
+#[parser(extras=Extra)]
+fn skip_token<'a>(input: Tokens<'a>) -> Option<usize> {
+    let before = input.offset();
+    let token = input.peek()?;
+    input.skip()?;
+
+    Ok(match token {
+        Token::Newline | Token::Indent | Token::Dedent => None,
+        _ => Some(input.span_since(before).end),
+    })
 }
 
+#[parser(extras=Extra)]
+#[instrument(level = "trace", skip(input), ret, err, parent = None)]
+fn parse_doctype<'a>(input: Tokens<'a>) -> Doctype {
+    let before = input.offset();
+    // the caller already checked that this is `doctype`
+    input.skip()?;
+
+    // plain `doctype` is HTML5, like in Pug
+    let mut value = "html".to_string();
+    if let Ok(Token::WhiteSpace) = input.peek() {
+        input.skip()?;
+        value = parse_content(input)?.value;
+    }
+
+    Ok(Doctype {
+        value,
+        span: input.span_since(before),
+    })
+}
+
+#[parser(extras=Extra)]
+#[instrument(level = "trace", skip(input), ret, err, parent = None)]
+fn document<'a>(input: Tokens<'a>) -> Document {
+    let mut nodes = vec![];
+
+    if let Ok(Token::Text) = input.peek() {
+        let offset = input.save();
+        if parse_text(input)? == "doctype" {
+            input.rewind(offset);
+            nodes.push(Node::Doctype(parse_doctype(input)?));
+
+            if let Ok(Token::Newline) = input.peek() {
+                input.skip()?;
+            }
+        } else {
+            input.rewind(offset);
+        }
+    }
+
+    nodes.extend(file(input)?);
+    // a dedent out of a block that no line opened, like the one after an indented first line,
+    // only ends the file so far and not the whole document
+    loop {
+        match input.peek() {
+            Ok(Token::Newline | Token::Dedent) => input.skip()?,
+            Ok(_) => nodes.extend(file(input)?),
+            Err(_) => break,
+        }
+    }
+
+    Ok(Document { nodes })
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// The number of spaces per level of indentation. `None` takes it from the first line
+    /// that's indented with spaces.
+    pub indent_width: Option<usize>,
+}
+
+/// Parses `input`, stopping at the first error.
 #[instrument(level = "debug", ret, err)]
-pub fn parse(input: &str) -> Result<Vec<Tag>, crate::errors::Error> {
-    file.parse_with_context(
-        Stream::from_iter(crate::lexer::Lexer::new(input)).spanned(input.len()..input.len()),
-        input.to_owned(),
-    )
-    .map_err(Into::into)
+pub fn parse(input: &str) -> Result<Document, crate::errors::Error> {
+    let (document, errors) = parse_recovering(input, &ParseOptions::default());
+
+    match errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(document),
+    }
+}
+
+/// Parses `input`, skipping over lines that fail to parse. Every error is returned along with
+/// the rest of the document, where the broken lines are [`Node::Error`]s.
+#[instrument(level = "debug")]
+pub fn parse_recovering(
+    input: &str,
+    options: &ParseOptions,
+) -> (Document, Vec<crate::errors::Error>) {
+    let parser_errors = RefCell::default();
+    let context = Context {
+        source: input,
+        errors: &parser_errors,
+    };
+    let lexer_errors = RefCell::default();
+    let lexer = Lexer::new(input, options.indent_width, &lexer_errors);
+
+    let document = match document.parse_with_context(
+        Stream::from_iter(lexer).spanned(input.len()..input.len()),
+        context,
+    ) {
+        Ok(document) => document,
+        // only errors that can't be recovered from end up here, like running out of input
+        Err(error) => {
+            parser_errors.borrow_mut().push(error);
+            Document { nodes: vec![] }
+        }
+    };
+    let mut errors: Vec<crate::errors::Error> = lexer_errors
+        .into_inner()
+        .into_iter()
+        .map(Into::into)
+        .chain(
+            parser_errors
+                .into_inner()
+                .into_iter()
+                .map(|error| error.with_source(input).into()),
+        )
+        .collect();
+
+    // the parser trips over whatever the lexer couldn't make sense of, which is reported already
+    let unrecognized: Vec<_> = errors
+        .iter()
+        .filter_map(|error| match error {
+            crate::errors::Error::Lexer(LexerError::Unrecognized { span }) => Some(span.clone()),
+            _ => None,
+        })
+        .collect();
+    errors.retain(|error| match error {
+        crate::errors::Error::Parser(
+            ParserError::ExpectedToken {
+                found: Token::Error,
+                span,
+                ..
+            }
+            | ParserError::ExpectedEOF {
+                found: Token::Error,
+                span,
+                ..
+            },
+        ) => !unrecognized.contains(span),
+        _ => true,
+    });
+
+    // the lexer's errors come first, but they're reported in the order they're in the source
+    errors.sort_by_key(|error| error.span().start);
+    (document, errors)
 }
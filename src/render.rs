@@ -0,0 +1,279 @@
+use std::fmt;
+use std::io;
+
+use crate::ast::{AttrValue, Comment, Document, Node, Tag, Text};
+use crate::escape::{write_escaped_attr, write_escaped_text};
+
+/// How the output is written, for the parts where HTML and XML disagree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    /// HTML5: boolean attributes are written as `disabled`, void elements as `<br>`
+    #[default]
+    Html,
+    /// XHTML/XML: boolean attributes are written as `disabled="disabled"`, void elements as `<br/>`
+    Xml,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    /// `None` takes the mode from the document's doctype, or uses HTML if there is none
+    pub mode: Option<Mode>,
+    /// Pretty-print the output, indenting every level with this string. `None` writes
+    /// everything on one line.
+    pub indent: Option<String>,
+}
+
+/// Elements that can't have any content, which the parser makes sure of
+pub(crate) const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements that are laid out as part of the surrounding text, so pretty-printing must not put
+/// whitespace around them
+const INLINE_ELEMENTS: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "br", "button", "cite", "code", "data", "dfn", "em", "i",
+    "img", "input", "kbd", "label", "mark", "q", "s", "samp", "select", "small", "span", "strong",
+    "sub", "sup", "time", "u", "var", "wbr",
+];
+
+/// Elements whose whitespace is rendered as-is, so pretty-printing must not touch their insides
+const WHITESPACE_SENSITIVE_ELEMENTS: &[&str] = &["pre", "textarea"];
+
+/// Elements whose text isn't HTML, so the text in their `script.`-style blocks is written as-is.
+/// Text anywhere else is escaped like for any other element.
+pub(crate) const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+/// Writes `document` as HTML into `out`, without building any intermediate strings.
+pub fn render<W: fmt::Write>(
+    out: &mut W,
+    document: &Document,
+    options: &RenderOptions,
+) -> fmt::Result {
+    let mode = options
+        .mode
+        .or(document.doctype().map(|doctype| doctype.mode()))
+        .unwrap_or_default();
+
+    Renderer { out, options, mode }.nodes(&document.nodes)
+}
+
+/// Like [`render`], but for a bare list of nodes outside of a document.
+pub fn render_nodes<W: fmt::Write>(
+    out: &mut W,
+    nodes: &[Node],
+    options: &RenderOptions,
+) -> fmt::Result {
+    let mode = options.mode.unwrap_or_default();
+    Renderer { out, options, mode }.nodes(nodes)
+}
+
+pub(crate) fn render_tag<W: fmt::Write>(
+    out: &mut W,
+    tag: &Tag,
+    options: &RenderOptions,
+) -> fmt::Result {
+    let mode = options.mode.unwrap_or_default();
+    Renderer { out, options, mode }.tag(tag, Some(0))
+}
+
+/// Like [`render`], but for byte sinks like files, sockets and stdout.
+pub fn render_io<W: io::Write>(
+    out: W,
+    document: &Document,
+    options: &RenderOptions,
+) -> io::Result<()> {
+    let mut adapter = IoAdapter {
+        inner: out,
+        error: None,
+    };
+
+    render(&mut adapter, document, options).map_err(|fmt::Error| {
+        adapter
+            .error
+            .unwrap_or_else(|| io::Error::other("formatting error while rendering"))
+    })
+}
+
+/// Lets the renderer write into an [`io::Write`], keeping the actual I/O error around since
+/// [`fmt::Error`] can't carry it
+struct IoAdapter<W> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> fmt::Write for IoAdapter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|error| {
+            self.error = Some(error);
+            fmt::Error
+        })
+    }
+}
+
+struct Renderer<'a, W> {
+    out: &'a mut W,
+    options: &'a RenderOptions,
+    mode: Mode,
+}
+
+impl<W: fmt::Write> Renderer<'_, W> {
+    fn nodes(&mut self, nodes: &[Node]) -> fmt::Result {
+        for (i, node) in nodes.iter().filter(|node| is_rendered(node)).enumerate() {
+            // pretty-printed top-level nodes go on their own lines too
+            if i > 0 && self.options.indent.is_some() {
+                self.out.write_char('\n')?;
+            }
+            self.node(node, Some(0))?;
+        }
+
+        Ok(())
+    }
+
+    fn node(&mut self, node: &Node, depth: Option<usize>) -> fmt::Result {
+        match node {
+            Node::Element(tag) => self.tag(tag, depth),
+            Node::Text(text) => self.text(text),
+            Node::Comment(comment) => self.comment(comment),
+            Node::Doctype(doctype) => self.out.write_str(&doctype.declaration()),
+            Node::Error(_) => Ok(()),
+        }
+    }
+
+    fn text(&mut self, text: &Text) -> fmt::Result {
+        if text.raw {
+            self.out.write_str(&text.text)
+        } else {
+            write_escaped_text(self.out, &text.text)
+        }
+    }
+
+    fn comment(&mut self, comment: &Comment) -> fmt::Result {
+        if comment.buffered {
+            write!(self.out, "<!--{}-->", comment.text)?;
+        }
+
+        Ok(())
+    }
+
+    /// `depth` is the nesting level when pretty-printing, or `None` when this element has to be
+    /// written on one line
+    fn tag(&mut self, tag: &Tag, depth: Option<usize>) -> fmt::Result {
+        let name = tag.name.as_str();
+
+        write!(self.out, "<{name}")?;
+        self.attrs(tag)?;
+
+        if tag.self_closing {
+            return self.out.write_str("/>");
+        }
+
+        if VOID_ELEMENTS.contains(&name) {
+            return match self.mode {
+                Mode::Html => self.out.write_char('>'),
+                Mode::Xml => self.out.write_str("/>"),
+            };
+        }
+
+        self.out.write_char('>')?;
+
+        match depth {
+            Some(depth) if self.options.indent.is_some() && !is_inline(tag) => {
+                for child in tag.children.iter().filter(|child| is_rendered(child)) {
+                    self.newline(depth + 1)?;
+                    self.node(child, Some(depth + 1))?;
+                }
+                self.newline(depth)?;
+            }
+            _ => {
+                for child in &tag.children {
+                    self.node(child, None)?;
+                }
+            }
+        }
+
+        write!(self.out, "</{name}>")
+    }
+
+    fn attrs(&mut self, tag: &Tag) -> fmt::Result {
+        // id and class always come first (in that order), followed by the other attributes in
+        // source order, so the same input always renders to the same output
+        if let Some(id) = &tag.id {
+            self.attr("id", Some(id), false)?;
+        }
+
+        if !tag.classes.is_empty() {
+            self.out.write_str(" class=\"")?;
+            for (i, class) in tag.classes.iter().enumerate() {
+                if i > 0 {
+                    self.out.write_char(' ')?;
+                }
+                write_escaped_attr(self.out, class)?;
+            }
+            self.out.write_char('"')?;
+        }
+
+        for attr in &tag.attrs {
+            match &attr.value {
+                Some(AttrValue { value, raw }) => self.attr(&attr.name, Some(value), *raw)?,
+                None => self.attr(&attr.name, None, false)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn attr(&mut self, name: &str, value: Option<&str>, raw: bool) -> fmt::Result {
+        match (value, self.mode) {
+            (Some(value), _) => {
+                write!(self.out, " {name}=\"")?;
+                if raw {
+                    self.out.write_str(value)?;
+                } else {
+                    write_escaped_attr(self.out, value)?;
+                }
+                self.out.write_char('"')
+            }
+            (None, Mode::Html) => write!(self.out, " {name}"),
+            (None, Mode::Xml) => write!(self.out, " {name}=\"{name}\""),
+        }
+    }
+
+    fn newline(&mut self, depth: usize) -> fmt::Result {
+        self.out.write_char('\n')?;
+        if let Some(indent) = &self.options.indent {
+            for _ in 0..depth {
+                self.out.write_str(indent)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether pretty-printing has to keep this element on one line to not change its meaning
+fn is_inline(tag: &Tag) -> bool {
+    let name = tag.name.as_str();
+
+    tag.children.is_empty()
+        || INLINE_ELEMENTS.contains(&name)
+        || WHITESPACE_SENSITIVE_ELEMENTS.contains(&name)
+        // whitespace next to text or inline children would show up on the page
+        || tag.children.iter().any(|child| match child {
+            Node::Element(child) => INLINE_ELEMENTS.contains(&child.name.as_str()),
+            Node::Text(_) => true,
+            Node::Comment(_) | Node::Doctype(_) | Node::Error(_) => false,
+        })
+}
+
+/// Whether `node` shows up in the output at all, so pretty-printing doesn't leave empty lines for
+/// `//-` comments and lines that failed to parse
+fn is_rendered(node: &Node) -> bool {
+    !matches!(
+        node,
+        Node::Comment(Comment {
+            buffered: false,
+            ..
+        }) | Node::Error(_)
+    )
+}